@@ -0,0 +1,16 @@
+-- Add migration script here
+CREATE TABLE sync_job (
+    sync_job_id SERIAL PRIMARY KEY,
+    repository_id INTEGER NOT NULL REFERENCES repository(repository_id) ON DELETE CASCADE,
+    github_token TEXT NOT NULL,
+    status TEXT NOT NULL DEFAULT 'queued'
+        CHECK (status IN ('queued', 'running', 'succeeded', 'failed')),
+    error TEXT,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    started_at TIMESTAMP WITH TIME ZONE,
+    finished_at TIMESTAMP WITH TIME ZONE
+);
+
+-- Workers claim queued jobs in FIFO order
+CREATE INDEX idx_sync_job_queued ON sync_job (created_at, sync_job_id) WHERE status = 'queued';
+CREATE INDEX idx_sync_job_repository_id ON sync_job (repository_id);
@@ -0,0 +1,4 @@
+-- Add migration script here
+ALTER TABLE sync_job
+    ADD COLUMN pages_fetched INTEGER NOT NULL DEFAULT 0,
+    ADD COLUMN commits_inserted INTEGER NOT NULL DEFAULT 0;
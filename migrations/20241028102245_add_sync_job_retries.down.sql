@@ -0,0 +1,14 @@
+-- Add migration script here
+DROP INDEX IF EXISTS idx_sync_job_dead;
+DROP INDEX IF EXISTS idx_sync_job_queued;
+CREATE INDEX idx_sync_job_queued ON sync_job (created_at, sync_job_id) WHERE status = 'queued';
+
+ALTER TABLE sync_job DROP CONSTRAINT sync_job_status_check;
+UPDATE sync_job SET status = 'failed' WHERE status = 'dead';
+ALTER TABLE sync_job ADD CONSTRAINT sync_job_status_check
+    CHECK (status IN ('queued', 'running', 'succeeded', 'failed'));
+
+ALTER TABLE sync_job
+    DROP COLUMN IF EXISTS attempts,
+    DROP COLUMN IF EXISTS max_attempts,
+    DROP COLUMN IF EXISTS run_after;
@@ -0,0 +1,18 @@
+-- Add migration script here
+ALTER TABLE sync_job
+    ADD COLUMN attempts INTEGER NOT NULL DEFAULT 0,
+    ADD COLUMN max_attempts INTEGER NOT NULL DEFAULT 5,
+    ADD COLUMN run_after TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP;
+
+UPDATE sync_job SET run_after = created_at;
+
+-- Jobs that exhaust their attempts are dead-lettered instead of failed
+ALTER TABLE sync_job DROP CONSTRAINT sync_job_status_check;
+UPDATE sync_job SET status = 'dead' WHERE status = 'failed';
+ALTER TABLE sync_job ADD CONSTRAINT sync_job_status_check
+    CHECK (status IN ('queued', 'running', 'succeeded', 'dead'));
+
+-- Retried jobs wait behind newer ones, so the queue is ordered by run_after
+DROP INDEX idx_sync_job_queued;
+CREATE INDEX idx_sync_job_queued ON sync_job (run_after, sync_job_id) WHERE status = 'queued';
+CREATE INDEX idx_sync_job_dead ON sync_job (finished_at) WHERE status = 'dead';
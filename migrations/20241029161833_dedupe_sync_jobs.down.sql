@@ -0,0 +1,2 @@
+-- Add migration script here
+DROP INDEX IF EXISTS idx_sync_job_one_active_per_repository;
@@ -0,0 +1,18 @@
+-- Add migration script here
+-- Jobs left running by a stopped server are requeued on startup anyway
+UPDATE sync_job SET status = 'queued', started_at = NULL WHERE status = 'running';
+
+-- Keep only the oldest queued job for each repository
+DELETE FROM sync_job j
+WHERE j.status = 'queued'
+AND EXISTS (
+    SELECT 1 FROM sync_job o
+    WHERE o.repository_id = j.repository_id
+    AND o.status = 'queued'
+    AND (o.run_after, o.sync_job_id) < (j.run_after, j.sync_job_id)
+);
+
+-- At most one queued or running sync per repository
+CREATE UNIQUE INDEX idx_sync_job_one_active_per_repository
+ON sync_job (repository_id)
+WHERE status IN ('queued', 'running');
@@ -0,0 +1,15 @@
+-- Add migration script here
+-- Overrides for how often the scheduler re-syncs a repository, either
+-- directly or for every repository in a collection
+CREATE TABLE sync_schedule (
+    sync_schedule_id SERIAL PRIMARY KEY,
+    repository_id INTEGER REFERENCES repository(repository_id) ON DELETE CASCADE,
+    collection_id INTEGER REFERENCES collection(collection_id) ON DELETE CASCADE,
+    interval_minutes INTEGER NOT NULL CHECK (interval_minutes > 0),
+    enabled BOOLEAN NOT NULL DEFAULT true,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    CHECK ((repository_id IS NULL) <> (collection_id IS NULL)),
+    UNIQUE (repository_id),
+    UNIQUE (collection_id)
+);
@@ -0,0 +1,12 @@
+-- Add migration script here
+DROP INDEX IF EXISTS idx_sync_job_one_active_per_repository;
+DELETE FROM sync_job WHERE status = 'cancelled';
+CREATE UNIQUE INDEX idx_sync_job_one_active_per_repository
+ON sync_job (repository_id)
+WHERE status IN ('queued', 'running');
+
+ALTER TABLE sync_job DROP CONSTRAINT sync_job_status_check;
+ALTER TABLE sync_job ADD CONSTRAINT sync_job_status_check
+    CHECK (status IN ('queued', 'running', 'succeeded', 'dead'));
+
+ALTER TABLE sync_job DROP COLUMN IF EXISTS cancel_requested;
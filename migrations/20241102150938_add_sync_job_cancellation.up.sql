@@ -0,0 +1,12 @@
+-- Add migration script here
+ALTER TABLE sync_job ADD COLUMN cancel_requested BOOLEAN NOT NULL DEFAULT false;
+
+ALTER TABLE sync_job DROP CONSTRAINT sync_job_status_check;
+ALTER TABLE sync_job ADD CONSTRAINT sync_job_status_check
+    CHECK (status IN ('queued', 'running', 'succeeded', 'dead', 'cancelled'));
+
+-- A running job that is being cancelled no longer blocks a new sync
+DROP INDEX idx_sync_job_one_active_per_repository;
+CREATE UNIQUE INDEX idx_sync_job_one_active_per_repository
+ON sync_job (repository_id)
+WHERE status IN ('queued', 'running') AND NOT cancel_requested;
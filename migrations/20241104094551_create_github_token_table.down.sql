@@ -0,0 +1,8 @@
+-- Add migration script here
+DROP INDEX IF EXISTS idx_sync_job_running_account;
+DELETE FROM sync_job WHERE status IN ('queued', 'running');
+ALTER TABLE sync_job
+    DROP COLUMN IF EXISTS account_id,
+    ADD COLUMN github_token TEXT NOT NULL DEFAULT '';
+ALTER TABLE sync_job ALTER COLUMN github_token DROP DEFAULT;
+DROP TABLE IF EXISTS github_token;
@@ -0,0 +1,17 @@
+-- Add migration script here
+-- GitHub access tokens, encrypted by the application before they are stored
+CREATE TABLE github_token (
+    account_id INTEGER PRIMARY KEY REFERENCES account(account_id) ON DELETE CASCADE,
+    encrypted_token BYTEA NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+-- Jobs reference the account whose token they run with instead of carrying
+-- the token. Jobs without an account use the server's own GitHub token, so
+-- existing jobs fall back to it.
+ALTER TABLE sync_job
+    ADD COLUMN account_id INTEGER REFERENCES account(account_id) ON DELETE CASCADE,
+    DROP COLUMN github_token;
+
+CREATE INDEX idx_sync_job_running_account ON sync_job (account_id) WHERE status = 'running';
@@ -0,0 +1,8 @@
+-- Add migration script here
+CREATE TABLE repository_branch (
+    repository_id INTEGER NOT NULL REFERENCES repository(repository_id) ON DELETE CASCADE,
+    branch TEXT NOT NULL,
+    head_sha TEXT NOT NULL,
+    synced_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (repository_id, branch)
+);
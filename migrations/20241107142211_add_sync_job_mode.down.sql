@@ -0,0 +1,4 @@
+-- Add migration script here
+ALTER TABLE sync_job
+    DROP COLUMN IF EXISTS commits_seen,
+    DROP COLUMN IF EXISTS mode;
@@ -0,0 +1,5 @@
+-- Add migration script here
+ALTER TABLE sync_job
+    ADD COLUMN mode TEXT NOT NULL DEFAULT 'incremental'
+        CHECK (mode IN ('incremental', 'full')),
+    ADD COLUMN commits_seen INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,3 @@
+-- Add migration script here
+DROP TABLE IF EXISTS commit_branch;
+ALTER TABLE repository DROP COLUMN IF EXISTS branch_patterns;
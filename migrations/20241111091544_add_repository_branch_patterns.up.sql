@@ -0,0 +1,16 @@
+-- Add migration script here
+ALTER TABLE repository ADD COLUMN branch_patterns TEXT[] NOT NULL DEFAULT '{}';
+
+CREATE TABLE commit_branch (
+    commit_id INTEGER NOT NULL REFERENCES commit(commit_id) ON DELETE CASCADE,
+    branch TEXT NOT NULL,
+    PRIMARY KEY (commit_id, branch)
+);
+
+CREATE INDEX idx_commit_branch_branch ON commit_branch(branch);
+
+-- Until now only the default branch was synced, so existing commits belong to it
+INSERT INTO commit_branch (commit_id, branch)
+SELECT c.commit_id, rb.branch
+FROM commit c
+JOIN repository_branch rb ON rb.repository_id = c.repository_id;
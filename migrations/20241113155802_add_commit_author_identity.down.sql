@@ -0,0 +1,11 @@
+-- Add migration script here
+DROP INDEX IF EXISTS idx_commit_contributor_key;
+ALTER TABLE commit
+    DROP COLUMN IF EXISTS contributor_key,
+    DROP COLUMN IF EXISTS committer_github_id,
+    DROP COLUMN IF EXISTS committer_login,
+    DROP COLUMN IF EXISTS committer_email,
+    DROP COLUMN IF EXISTS committer_name,
+    DROP COLUMN IF EXISTS author_github_id,
+    DROP COLUMN IF EXISTS author_login,
+    DROP COLUMN IF EXISTS author_email;
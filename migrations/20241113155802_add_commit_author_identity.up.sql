@@ -0,0 +1,21 @@
+-- Add migration script here
+ALTER TABLE commit
+    ADD COLUMN author_email TEXT,
+    ADD COLUMN author_login TEXT,
+    ADD COLUMN author_github_id BIGINT,
+    ADD COLUMN committer_name TEXT,
+    ADD COLUMN committer_email TEXT,
+    ADD COLUMN committer_login TEXT,
+    ADD COLUMN committer_github_id BIGINT;
+
+-- Prefer the GitHub account, which survives renames and differing git
+-- configs, then the email address, then the free-text git author name
+ALTER TABLE commit ADD COLUMN contributor_key TEXT GENERATED ALWAYS AS (
+    COALESCE(
+        'github:' || author_github_id::text,
+        'email:' || lower(author_email),
+        'name:' || author
+    )
+) STORED;
+
+CREATE INDEX idx_commit_contributor_key ON commit(contributor_key);
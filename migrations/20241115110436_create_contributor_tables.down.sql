@@ -0,0 +1,3 @@
+-- Add migration script here
+DROP TABLE IF EXISTS contributor_alias;
+DROP TABLE IF EXISTS contributor;
@@ -0,0 +1,51 @@
+-- Add migration script here
+CREATE TABLE contributor (
+    contributor_id SERIAL PRIMARY KEY,
+    display_name TEXT NOT NULL,
+    -- Set once an identity has been merged or split by hand, after which the
+    -- automatic merger leaves it alone
+    locked BOOLEAN NOT NULL DEFAULT false,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+-- Aliases use the same form as commit.contributor_key: github:<id>,
+-- email:<lowercased address> or name:<git author name>
+CREATE TABLE contributor_alias (
+    alias TEXT PRIMARY KEY,
+    contributor_id INTEGER NOT NULL REFERENCES contributor(contributor_id) ON DELETE CASCADE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_contributor_alias_contributor ON contributor_alias(contributor_id);
+
+-- One contributor per identity seen so far
+ALTER TABLE contributor ADD COLUMN seed_key TEXT;
+
+INSERT INTO contributor (display_name, seed_key)
+SELECT DISTINCT ON (contributor_key) COALESCE(author_login, author), contributor_key
+FROM commit
+ORDER BY contributor_key, date DESC;
+
+INSERT INTO contributor_alias (alias, contributor_id)
+SELECT seed_key, contributor_id FROM contributor;
+
+ALTER TABLE contributor DROP COLUMN seed_key;
+
+-- Emails committed with from a GitHub account belong to that account
+INSERT INTO contributor_alias (alias, contributor_id)
+SELECT DISTINCT ON (e.alias) e.alias, e.contributor_id
+FROM (
+    SELECT 'email:' || lower(c.author_email) AS alias, a.contributor_id, COUNT(*) AS commits
+    FROM commit c
+    JOIN contributor_alias a ON a.alias = c.contributor_key
+    WHERE c.author_github_id IS NOT NULL AND c.author_email IS NOT NULL
+    GROUP BY 1, 2
+) e
+ORDER BY e.alias, e.commits DESC
+ON CONFLICT (alias) DO UPDATE SET contributor_id = EXCLUDED.contributor_id;
+
+DELETE FROM contributor c
+WHERE NOT EXISTS (
+    SELECT 1 FROM contributor_alias a WHERE a.contributor_id = c.contributor_id
+);
@@ -0,0 +1,3 @@
+-- Add migration script here
+ALTER TABLE commit DROP COLUMN IF EXISTS is_bot;
+DROP TABLE IF EXISTS bot_pattern;
@@ -0,0 +1,31 @@
+-- Add migration script here
+-- Patterns are case-insensitive POSIX regular expressions matched against a
+-- commit's author name, GitHub login and email. Global patterns (no
+-- collection) are applied when commits are ingested; a collection's own
+-- patterns are applied when its growth accounting excludes bots.
+CREATE TABLE bot_pattern (
+    bot_pattern_id SERIAL PRIMARY KEY,
+    collection_id INTEGER REFERENCES collection(collection_id) ON DELETE CASCADE,
+    pattern TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    UNIQUE NULLS NOT DISTINCT (collection_id, pattern)
+);
+
+INSERT INTO bot_pattern (pattern) VALUES
+    ('\[bot\]$'),
+    ('^dependabot'),
+    ('^renovate'),
+    ('^github-actions'),
+    ('^greenkeeper'),
+    ('^snyk-bot$'),
+    ('^pre-commit-ci');
+
+ALTER TABLE commit ADD COLUMN is_bot BOOLEAN NOT NULL DEFAULT false;
+
+UPDATE commit c
+SET is_bot = true
+WHERE EXISTS (
+    SELECT 1 FROM bot_pattern b
+    WHERE b.collection_id IS NULL
+    AND (c.author ~* b.pattern OR c.author_login ~* b.pattern OR c.author_email ~* b.pattern)
+);
@@ -0,0 +1,6 @@
+-- Add migration script here
+DROP INDEX IF EXISTS idx_commit_missing_stats;
+ALTER TABLE commit
+    DROP COLUMN IF EXISTS files_changed,
+    DROP COLUMN IF EXISTS deletions,
+    DROP COLUMN IF EXISTS additions;
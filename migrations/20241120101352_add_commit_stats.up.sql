@@ -0,0 +1,8 @@
+-- Add migration script here
+-- NULL until the commit's stats have been fetched
+ALTER TABLE commit
+    ADD COLUMN additions INTEGER,
+    ADD COLUMN deletions INTEGER,
+    ADD COLUMN files_changed INTEGER;
+
+CREATE INDEX idx_commit_missing_stats ON commit(repository_id, date DESC) WHERE additions IS NULL;
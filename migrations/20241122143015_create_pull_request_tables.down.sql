@@ -0,0 +1,5 @@
+-- Add migration script here
+ALTER TABLE repository DROP COLUMN IF EXISTS pull_requests_synced_at;
+DROP TABLE IF EXISTS pull_request_review_comment;
+DROP TABLE IF EXISTS pull_request_review;
+DROP TABLE IF EXISTS pull_request;
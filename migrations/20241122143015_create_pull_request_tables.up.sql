@@ -0,0 +1,52 @@
+-- Add migration script here
+-- contributor_key matches commit.contributor_key for GitHub accounts and is
+-- NULL for deleted users
+CREATE TABLE pull_request (
+    pull_request_id SERIAL PRIMARY KEY,
+    repository_id INTEGER NOT NULL REFERENCES repository(repository_id) ON DELETE CASCADE,
+    number INTEGER NOT NULL,
+    title TEXT,
+    state TEXT NOT NULL,
+    author_login TEXT,
+    author_github_id BIGINT,
+    is_bot BOOLEAN NOT NULL DEFAULT false,
+    contributor_key TEXT GENERATED ALWAYS AS ('github:' || author_github_id::text) STORED,
+    created_at TIMESTAMPTZ NOT NULL,
+    updated_at TIMESTAMPTZ NOT NULL,
+    merged_at TIMESTAMPTZ,
+    closed_at TIMESTAMPTZ,
+    UNIQUE (repository_id, number)
+);
+
+CREATE INDEX idx_pull_request_repository_created ON pull_request(repository_id, created_at);
+
+CREATE TABLE pull_request_review (
+    -- GitHub's ids are used so re-fetched reviews and comments are upserted
+    review_id BIGINT PRIMARY KEY,
+    pull_request_id INTEGER NOT NULL REFERENCES pull_request(pull_request_id) ON DELETE CASCADE,
+    state TEXT NOT NULL,
+    reviewer_login TEXT,
+    reviewer_github_id BIGINT,
+    is_bot BOOLEAN NOT NULL DEFAULT false,
+    contributor_key TEXT GENERATED ALWAYS AS ('github:' || reviewer_github_id::text) STORED,
+    submitted_at TIMESTAMPTZ
+);
+
+CREATE INDEX idx_pull_request_review_pull_request ON pull_request_review(pull_request_id);
+
+CREATE TABLE pull_request_review_comment (
+    comment_id BIGINT PRIMARY KEY,
+    pull_request_id INTEGER NOT NULL REFERENCES pull_request(pull_request_id) ON DELETE CASCADE,
+    review_id BIGINT,
+    author_login TEXT,
+    author_github_id BIGINT,
+    is_bot BOOLEAN NOT NULL DEFAULT false,
+    contributor_key TEXT GENERATED ALWAYS AS ('github:' || author_github_id::text) STORED,
+    created_at TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX idx_pull_request_review_comment_pull_request ON pull_request_review_comment(pull_request_id);
+
+-- Pull requests and review comments updated after this are fetched on the
+-- next sync
+ALTER TABLE repository ADD COLUMN pull_requests_synced_at TIMESTAMPTZ;
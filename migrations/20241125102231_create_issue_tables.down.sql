@@ -0,0 +1,5 @@
+-- Add migration script here
+ALTER TABLE repository DROP COLUMN IF EXISTS issues_synced_at;
+DROP TABLE IF EXISTS issue_close;
+DROP TABLE IF EXISTS issue_comment;
+DROP TABLE IF EXISTS issue;
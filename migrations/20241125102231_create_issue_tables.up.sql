@@ -0,0 +1,48 @@
+-- Add migration script here
+-- Pull requests also show up in GitHub's issue listings; they are kept out
+-- of these tables
+CREATE TABLE issue (
+    issue_id SERIAL PRIMARY KEY,
+    repository_id INTEGER NOT NULL REFERENCES repository(repository_id) ON DELETE CASCADE,
+    number INTEGER NOT NULL,
+    title TEXT,
+    state TEXT NOT NULL,
+    author_login TEXT,
+    author_github_id BIGINT,
+    is_bot BOOLEAN NOT NULL DEFAULT false,
+    contributor_key TEXT GENERATED ALWAYS AS ('github:' || author_github_id::text) STORED,
+    created_at TIMESTAMPTZ NOT NULL,
+    updated_at TIMESTAMPTZ NOT NULL,
+    closed_at TIMESTAMPTZ,
+    UNIQUE (repository_id, number)
+);
+
+CREATE INDEX idx_issue_repository_created ON issue(repository_id, created_at);
+
+CREATE TABLE issue_comment (
+    comment_id BIGINT PRIMARY KEY,
+    issue_id INTEGER NOT NULL REFERENCES issue(issue_id) ON DELETE CASCADE,
+    author_login TEXT,
+    author_github_id BIGINT,
+    is_bot BOOLEAN NOT NULL DEFAULT false,
+    contributor_key TEXT GENERATED ALWAYS AS ('github:' || author_github_id::text) STORED,
+    created_at TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX idx_issue_comment_issue ON issue_comment(issue_id);
+
+-- Who closed an issue is only available from its events, and an issue can be
+-- closed more than once
+CREATE TABLE issue_close (
+    event_id BIGINT PRIMARY KEY,
+    issue_id INTEGER NOT NULL REFERENCES issue(issue_id) ON DELETE CASCADE,
+    actor_login TEXT,
+    actor_github_id BIGINT,
+    is_bot BOOLEAN NOT NULL DEFAULT false,
+    contributor_key TEXT GENERATED ALWAYS AS ('github:' || actor_github_id::text) STORED,
+    created_at TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX idx_issue_close_issue ON issue_close(issue_id);
+
+ALTER TABLE repository ADD COLUMN issues_synced_at TIMESTAMPTZ;
@@ -0,0 +1,2 @@
+-- Add migration script here
+-- The queued syncs are not undone; they only fill in missing identities
@@ -0,0 +1,29 @@
+-- Add migration script here
+-- Commits stored before author identities were recorded only have the git
+-- author name, so their contributor_key never matches the GitHub account or
+-- email their author is known by now. A full sync fills the identities in.
+UPDATE sync_job SET mode = 'full'
+WHERE status IN ('queued', 'running') AND NOT cancel_requested
+AND repository_id IN (
+    SELECT repository_id FROM commit
+    WHERE author_email IS NULL AND author_github_id IS NULL
+);
+
+-- Run as the account whose sync last succeeded, like the scheduler does
+INSERT INTO sync_job (repository_id, account_id, mode)
+SELECT r.repository_id, (
+    SELECT j.account_id FROM sync_job j
+    JOIN github_token t ON t.account_id = j.account_id
+    WHERE j.repository_id = r.repository_id AND j.status = 'succeeded'
+    ORDER BY j.finished_at DESC
+    LIMIT 1
+), 'full'
+FROM repository r
+WHERE EXISTS (
+    SELECT 1 FROM commit c
+    WHERE c.repository_id = r.repository_id
+    AND c.author_email IS NULL AND c.author_github_id IS NULL
+)
+ON CONFLICT (repository_id)
+WHERE status IN ('queued', 'running') AND NOT cancel_requested
+DO NOTHING;
@@ -0,0 +1,3 @@
+-- Add migration script here
+DROP INDEX IF EXISTS idx_pull_request_reviews_pending;
+ALTER TABLE pull_request DROP COLUMN IF EXISTS reviews_synced_at;
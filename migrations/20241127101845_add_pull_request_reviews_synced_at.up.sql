@@ -0,0 +1,10 @@
+-- Add migration script here
+-- Reviews are fetched a pull request at a time after the listing; NULL or
+-- older than updated_at while the pull request's reviews need fetching
+ALTER TABLE pull_request ADD COLUMN reviews_synced_at TIMESTAMPTZ;
+
+-- Reviews were fetched along with every pull request synced so far
+UPDATE pull_request SET reviews_synced_at = updated_at;
+
+CREATE INDEX idx_pull_request_reviews_pending ON pull_request(repository_id, updated_at DESC)
+WHERE reviews_synced_at IS NULL OR reviews_synced_at < updated_at;
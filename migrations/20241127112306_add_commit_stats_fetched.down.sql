@@ -0,0 +1,4 @@
+-- Add migration script here
+DROP INDEX IF EXISTS idx_commit_missing_stats;
+ALTER TABLE commit DROP COLUMN IF EXISTS stats_fetched;
+CREATE INDEX idx_commit_missing_stats ON commit(repository_id, date DESC) WHERE additions IS NULL;
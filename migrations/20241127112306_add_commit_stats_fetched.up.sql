@@ -0,0 +1,8 @@
+-- Add migration script here
+-- Stats stay NULL for commits GitHub cannot compute a diff for, so whether
+-- they were fetched is recorded separately
+ALTER TABLE commit ADD COLUMN stats_fetched BOOLEAN NOT NULL DEFAULT false;
+UPDATE commit SET stats_fetched = true WHERE additions IS NOT NULL;
+
+DROP INDEX IF EXISTS idx_commit_missing_stats;
+CREATE INDEX idx_commit_missing_stats ON commit(repository_id, date DESC) WHERE NOT stats_fetched;
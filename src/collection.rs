@@ -1,8 +1,10 @@
 use crate::auth_utils::get_account_id;
 use crate::error::AppError;
 use crate::growth_accounting::{
-    ltv_cohorts_cumulative, mau_growth_accounting, mrr_growth_accounting,
-    LTVCohortsCumulativeResult, MAUGrowthAccountingResult, MRRGrowthAccountingResult,
+    dau_query, ltv_cohorts_cumulative, ltv_weekly_cohorts_cumulative, mau_growth_accounting,
+    mau_retention_by_cohort, mrr_growth_accounting, retention_triangle, stickiness, summarize,
+    DauScope, Granularity, GrowthAccountingParams, LTVCohortsCumulativeResult,
+    MAUGrowthAccountingResult, MRRGrowthAccountingResult, TrailingAverages,
 };
 use crate::repository::{upsert_repository, NewRepository, Repository};
 use crate::AppState;
@@ -475,81 +477,279 @@ pub async fn remove_repository_from_collection(
     }
 }
 
+pub async fn check_collection_owner(
+    pool: &PgPool,
+    req: &HttpRequest,
+    collection_id: i32,
+) -> Result<(), AppError> {
+    let account_id = get_account_id(req)?;
+    let collection = sqlx::query!(
+        r#"
+        SELECT owner_id FROM collection
+        WHERE collection_id = $1
+        "#,
+        collection_id
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    match collection {
+        Some(collection) if collection.owner_id == account_id => Ok(()),
+        Some(_) => Err(AppError::Unauthorized(
+            "You do not own this collection".into(),
+        )),
+        None => Err(AppError::NotFound("Collection not found".into())),
+    }
+}
+
+/// A repository's settings may be changed by the owners of the collections
+/// that contain it.
+pub async fn check_repository_owner(
+    pool: &PgPool,
+    req: &HttpRequest,
+    repository_id: i32,
+) -> Result<(), AppError> {
+    let account_id = get_account_id(req)?;
+    let owned = sqlx::query_scalar!(
+        r#"
+        SELECT EXISTS (
+            SELECT 1 FROM collection_repository cr
+            JOIN collection c ON c.collection_id = cr.collection_id
+            WHERE cr.repository_id = $1 AND c.owner_id = $2
+        ) AS "owned!"
+        "#,
+        repository_id,
+        account_id
+    )
+    .fetch_one(pool)
+    .await?;
+
+    if owned {
+        Ok(())
+    } else {
+        Err(AppError::Unauthorized(
+            "You do not own a collection containing this repository".into(),
+        ))
+    }
+}
+
+/// Reports on a collection are only available to its owner.
+async fn collection_scope(
+    pool: &PgPool,
+    req: &HttpRequest,
+    collection_id: i32,
+) -> Result<DauScope, AppError> {
+    check_collection_owner(pool, req, collection_id).await?;
+    Ok(DauScope::Collection(collection_id))
+}
+
+#[derive(Debug, Serialize)]
+pub struct BotPattern {
+    bot_pattern_id: i32,
+    /// None for the global patterns applied when commits are ingested.
+    collection_id: Option<i32>,
+    pattern: String,
+    created_at: DateTime<Utc>,
+}
+
+#[derive(Deserialize)]
+pub struct NewBotPattern {
+    pattern: String,
+}
+
+/// Lists the global bot patterns followed by the collection's own.
+pub async fn get_collection_bot_patterns(
+    state: web::Data<AppState>,
+    req: HttpRequest,
+    collection_id: web::Path<i32>,
+) -> Result<HttpResponse, AppError> {
+    let collection_id = collection_id.into_inner();
+    check_collection_owner(&state.db_pool, &req, collection_id).await?;
+
+    let patterns = sqlx::query_as!(
+        BotPattern,
+        r#"
+        SELECT bot_pattern_id, collection_id, pattern, created_at
+        FROM bot_pattern
+        WHERE collection_id IS NULL OR collection_id = $1
+        ORDER BY collection_id NULLS FIRST, bot_pattern_id
+        "#,
+        collection_id
+    )
+    .fetch_all(&state.db_pool)
+    .await?;
+
+    Ok(HttpResponse::Ok().json(patterns))
+}
+
+pub async fn add_collection_bot_pattern(
+    state: web::Data<AppState>,
+    req: HttpRequest,
+    collection_id: web::Path<i32>,
+    new_pattern: web::Json<NewBotPattern>,
+) -> Result<HttpResponse, AppError> {
+    let collection_id = collection_id.into_inner();
+    check_collection_owner(&state.db_pool, &req, collection_id).await?;
+
+    // Let Postgres reject patterns it cannot compile
+    if let Err(e) = sqlx::query!("SELECT '' ~* $1 AS matches", new_pattern.pattern)
+        .fetch_one(&state.db_pool)
+        .await
+    {
+        return Err(AppError::BadRequest(format!(
+            "Invalid bot pattern: {}",
+            e.as_database_error()
+                .map(|e| e.message().to_string())
+                .unwrap_or_else(|| e.to_string())
+        )));
+    }
+
+    let pattern = sqlx::query_as!(
+        BotPattern,
+        r#"
+        INSERT INTO bot_pattern (collection_id, pattern)
+        VALUES ($1, $2)
+        ON CONFLICT (collection_id, pattern) DO UPDATE SET pattern = EXCLUDED.pattern
+        RETURNING bot_pattern_id, collection_id, pattern, created_at
+        "#,
+        collection_id,
+        new_pattern.pattern
+    )
+    .fetch_one(&state.db_pool)
+    .await?;
+
+    Ok(HttpResponse::Created().json(pattern))
+}
+
+pub async fn remove_collection_bot_pattern(
+    state: web::Data<AppState>,
+    req: HttpRequest,
+    path: web::Path<(i32, i32)>,
+) -> Result<HttpResponse, AppError> {
+    let (collection_id, bot_pattern_id) = path.into_inner();
+    check_collection_owner(&state.db_pool, &req, collection_id).await?;
+
+    let result = sqlx::query!(
+        r#"
+        DELETE FROM bot_pattern
+        WHERE collection_id = $1 AND bot_pattern_id = $2
+        "#,
+        collection_id,
+        bot_pattern_id
+    )
+    .execute(&state.db_pool)
+    .await?;
+
+    if result.rows_affected() > 0 {
+        Ok(HttpResponse::NoContent().finish())
+    } else {
+        Ok(HttpResponse::NotFound().finish())
+    }
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct GrowthAccountingResult {
+    granularity: Granularity,
     mau_growth_accounting: Vec<MAUGrowthAccountingResult>,
     mrr_growth_accounting: Vec<MRRGrowthAccountingResult>,
     ltv_cumulative_cohort: Vec<LTVCohortsCumulativeResult>,
+    summary: Vec<TrailingAverages>,
 }
 
 pub async fn get_collection_growth_accounting(
     state: web::Data<AppState>,
     req: HttpRequest,
     collection_id: web::Path<i32>,
+    params: web::Query<GrowthAccountingParams>,
 ) -> Result<HttpResponse, AppError> {
     // Implement the logic to calculate growth accounting for all repositories in the collection
     // This will involve joining the collections, collection_repositories, and commit tables,
     // and then performing the growth accounting calculations
-    let account_id = get_account_id(&req)?;
+    params
+        .validate()
+        .map_err(|e| AppError::BadRequest(e.into()))?;
     let collection_id = collection_id.into_inner();
-    let collection = sqlx::query!(
-        r#"
-        SELECT owner_id FROM collection
-        WHERE collection_id = $1
-        "#,
-        collection_id
-    )
-    .fetch_optional(&state.db_pool)
-    .await?;
+    check_collection_owner(&state.db_pool, &req, collection_id).await?;
 
-    match collection {
-        Some(collection) if collection.owner_id == account_id => {
-            match fetch_growth_accounting(&state.db_pool, collection_id).await {
-                Ok(results) => Ok(HttpResponse::Ok().json(results)),
-                Err(e) => {
-                    error!("Error fetching growth accounting data: {:?}", e);
-                    Err(AppError::InternalServerError(
-                        "An error occurred while fetching growth accounting data".to_string(),
-                    ))
-                }
-            }
+    match fetch_growth_accounting(&state.db_pool, collection_id, &params).await {
+        Ok(results) => Ok(HttpResponse::Ok().json(results)),
+        Err(e) => {
+            error!("Error fetching growth accounting data: {:?}", e);
+            Err(AppError::InternalServerError(
+                "An error occurred while fetching growth accounting data".to_string(),
+            ))
         }
-        Some(_) => Err(AppError::Unauthorized(
-            "You do not own this collection".into(),
-        )),
-        None => Ok(HttpResponse::NotFound().finish()),
     }
 }
 
+pub async fn get_collection_stickiness(
+    state: web::Data<AppState>,
+    req: HttpRequest,
+    collection_id: web::Path<i32>,
+    params: web::Query<GrowthAccountingParams>,
+) -> Result<HttpResponse, AppError> {
+    params
+        .validate()
+        .map_err(|e| AppError::BadRequest(e.into()))?;
+    let scope = collection_scope(&state.db_pool, &req, collection_id.into_inner()).await?;
+
+    let dau = dau_query(scope, &params, None);
+    let results = stickiness(&state.db_pool, dau, &params).await?;
+    Ok(HttpResponse::Ok().json(results))
+}
+
+pub async fn get_collection_retention(
+    state: web::Data<AppState>,
+    req: HttpRequest,
+    collection_id: web::Path<i32>,
+    params: web::Query<GrowthAccountingParams>,
+) -> Result<HttpResponse, AppError> {
+    params
+        .validate()
+        .map_err(|e| AppError::BadRequest(e.into()))?;
+    let scope = collection_scope(&state.db_pool, &req, collection_id.into_inner()).await?;
+
+    let dau = dau_query(scope, &params, None);
+    let metric_dau = dau_query(scope, &params, params.metric);
+    let rows = mau_retention_by_cohort(&state.db_pool, dau, metric_dau, &params).await?;
+    Ok(HttpResponse::Ok().json(retention_triangle(rows)))
+}
+
+pub async fn get_collection_weekly_ltv(
+    state: web::Data<AppState>,
+    req: HttpRequest,
+    collection_id: web::Path<i32>,
+    params: web::Query<GrowthAccountingParams>,
+) -> Result<HttpResponse, AppError> {
+    params
+        .validate()
+        .map_err(|e| AppError::BadRequest(e.into()))?;
+    let scope = collection_scope(&state.db_pool, &req, collection_id.into_inner()).await?;
+
+    let dau = dau_query(scope, &params, params.metric);
+    let results = ltv_weekly_cohorts_cumulative(&state.db_pool, dau, &params).await?;
+    Ok(HttpResponse::Ok().json(results))
+}
+
 async fn fetch_growth_accounting(
     pool: &PgPool,
     collection_id: i32,
+    params: &GrowthAccountingParams,
 ) -> Result<GrowthAccountingResult, sqlx::Error> {
-    let dau_query = format!(
-        r#"
-        SELECT
-            author AS user_id,
-            date_trunc('day', "date") AS dt,
-            count(*) AS inc_amt
-        FROM
-            "commit" c
-            LEFT JOIN collection_repository cr ON cr.repository_id = c.repository_id
-        WHERE
-            cr.collection_id = {}
-        GROUP BY
-            1,
-            2
-        "#,
-        collection_id
-    );
+    let scope = DauScope::Collection(collection_id);
+    let activity_dau = dau_query(scope, params, None);
+    let metric_dau = dau_query(scope, params, params.metric);
 
-    let mau_ga = mau_growth_accounting(pool, dau_query.clone()).await?;
-    let mrr_ga = mrr_growth_accounting(pool, dau_query.clone()).await?;
-    let ltv_cumulative = ltv_cohorts_cumulative(pool, dau_query.clone()).await?;
+    let mau_ga = mau_growth_accounting(pool, activity_dau, params).await?;
+    let mrr_ga = mrr_growth_accounting(pool, metric_dau.clone(), params).await?;
+    let ltv_cumulative = ltv_cohorts_cumulative(pool, metric_dau, params).await?;
+    let summary = summarize(&mau_ga, &mrr_ga, params.granularity);
 
     Ok(GrowthAccountingResult {
+        granularity: params.granularity,
         mau_growth_accounting: mau_ga,
         mrr_growth_accounting: mrr_ga,
         ltv_cumulative_cohort: ltv_cumulative,
+        summary,
     })
 }
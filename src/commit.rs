@@ -1,138 +1,581 @@
+use crate::contributor::{commit_aliases, is_bot_account, link_contributor};
 use crate::error::AppError;
-use crate::job_queue::Job;
-use backoff::{Error as BackoffError, ExponentialBackoff};
-use chrono::{DateTime, Utc};
+use crate::github::with_backoff;
+use crate::job_queue::{is_cancel_requested, Job, SyncMode};
+use chrono::Utc;
+use glob::Pattern;
+use http::StatusCode;
 use log::info;
 use octocrab::models::repos::RepoCommit;
 use octocrab::Octocrab;
-use octocrab::Page;
+use serde::Deserialize;
 use sqlx::PgPool;
 
+/// Commits whose stats are fetched between rate limit and cancellation
+/// checks.
+const STATS_BATCH_SIZE: usize = 100;
+
+/// GitHub lists at most this many files with a commit, so a commit that
+/// reaches it may have changed more.
+const COMMIT_FILES_LIMIT: usize = 300;
+
+pub enum SyncOutcome {
+    Completed,
+    /// Stopped between pages because the job was cancelled. Commits from the
+    /// pages already fetched are kept.
+    Cancelled,
+}
+
+/// The part of GitHub's compare response the sync needs. `commits` are the
+/// commits reachable from the head but not from the base, oldest first.
+#[derive(Deserialize)]
+struct CommitComparison {
+    total_commits: usize,
+    commits: Vec<RepoCommit>,
+}
+
+/// Syncs the default branch and every branch matching the repository's
+/// branch patterns. Each branch is walked from its current head back to the
+/// head seen on the previous sync, so commits are found by ancestry rather
+/// than by author date. A full sync walks the whole history of the default
+/// branch and adds whatever commits are missing.
 pub async fn fetch_and_persist_commits(
     job: &Job,
     octocrab: &Octocrab,
     pool: &PgPool,
-) -> Result<(), AppError> {
-    let latest_commit_date = get_latest_commit_date(pool, job.repository_id).await?;
+) -> Result<SyncOutcome, AppError> {
+    let default_branch = fetch_default_branch(octocrab, &job.owner, &job.name).await?;
+    let branch_patterns = get_branch_patterns(pool, job.repository_id).await?;
+    let branches = fetch_branches(octocrab, &job.owner, &job.name).await?;
+
+    let default_head_sha = branches
+        .iter()
+        .find(|(branch, _)| *branch == default_branch)
+        .map(|(_, head_sha)| head_sha.clone())
+        .ok_or_else(|| {
+            AppError::NotFound(format!("Default branch {} not found", default_branch))
+        })?;
+
+    if let SyncOutcome::Cancelled = sync_branch(
+        job,
+        octocrab,
+        pool,
+        &default_branch,
+        &default_head_sha,
+        None,
+    )
+    .await?
+    {
+        return Ok(SyncOutcome::Cancelled);
+    }
+
+    // Other branches only add the commits that are not on the default
+    // branch, so shared history is stored and attributed once
+    for (branch, head_sha) in &branches {
+        if *branch == default_branch
+            || !branch_patterns
+                .iter()
+                .any(|pattern| pattern.matches(branch))
+        {
+            continue;
+        }
+        if let SyncOutcome::Cancelled = sync_branch(
+            job,
+            octocrab,
+            pool,
+            branch,
+            head_sha,
+            Some(&default_head_sha),
+        )
+        .await?
+        {
+            return Ok(SyncOutcome::Cancelled);
+        }
+    }
+
+    sqlx::query!(
+        "UPDATE repository SET indexed_at = $1 WHERE repository_id = $2",
+        Utc::now(),
+        job.repository_id
+    )
+    .execute(pool)
+    .await?;
+
     info!(
-        "Latest commit date for repository {}/{}: {:?}",
-        job.owner, job.name, latest_commit_date
+        "Updated indexed_at for repository {}/{} to {}",
+        job.owner,
+        job.name,
+        Utc::now()
     );
 
-    let mut page: u32 = 1;
-    let mut new_commits_found = false;
+    Ok(SyncOutcome::Completed)
+}
+
+/// Fetches additions, deletions and files changed for the repository's
+/// commits that do not have them yet, newest first. GitHub only returns
+/// stats one commit at a time, so the backfill stops once the token's
+/// remaining rate limit drops to `rate_limit_reserve` and carries on with
+/// the next sync. Stats stay NULL for commits whose diff is too large for
+/// GitHub to compute, as does files changed when GitHub truncated the file
+/// list.
+pub async fn backfill_commit_stats(
+    job: &Job,
+    octocrab: &Octocrab,
+    pool: &PgPool,
+    rate_limit_reserve: usize,
+) -> Result<SyncOutcome, AppError> {
+    let mut stats_fetched: usize = 0;
 
     loop {
-        let commits = fetch_commits_with_backoff(octocrab, &job.owner, &job.name, page).await?;
-
-        for commit in commits.items {
-            let author = commit
-                .commit
-                .author
-                .as_ref()
-                .map(|a| a.name.clone())
-                .unwrap_or_else(|| "Unknown".to_string());
-            let date = commit
-                .commit
-                .author
-                .and_then(|a| a.date)
-                .unwrap_or_else(|| chrono::Utc::now());
-
-            if let Some(latest_date) = latest_commit_date {
-                if date <= latest_date {
-                    continue;
-                }
+        let remaining = with_backoff(|| async { octocrab.ratelimit().get().await })
+            .await?
+            .resources
+            .core
+            .remaining;
+        if remaining <= rate_limit_reserve {
+            info!(
+                "Pausing stats backfill for repository {}/{} after {} commits, {} requests left",
+                job.owner, job.name, stats_fetched, remaining
+            );
+            return Ok(SyncOutcome::Completed);
+        }
+
+        let batch_size = (remaining - rate_limit_reserve).min(STATS_BATCH_SIZE) as i64;
+        let shas = sqlx::query_scalar!(
+            r#"
+            SELECT sha FROM commit
+            WHERE repository_id = $1 AND NOT stats_fetched
+            ORDER BY date DESC
+            LIMIT $2
+            "#,
+            job.repository_id,
+            batch_size
+        )
+        .fetch_all(pool)
+        .await?;
+        if shas.is_empty() {
+            if stats_fetched > 0 {
+                info!(
+                    "Fetched stats for {} commits of repository {}/{}",
+                    stats_fetched, job.owner, job.name
+                );
             }
+            return Ok(SyncOutcome::Completed);
+        }
 
-            new_commits_found = true;
+        for sha in &shas {
+            let stats = match with_backoff(|| async {
+                octocrab.commits(&job.owner, &job.name).get(sha).await
+            })
+            .await
+            {
+                Ok(commit) => {
+                    let (additions, deletions) = commit
+                        .stats
+                        .map(|stats| (stats.additions, stats.deletions))
+                        .unwrap_or_default();
+                    let files_changed = commit.files.map(|files| files.len()).unwrap_or(0);
+                    (
+                        Some(additions.unwrap_or(0) as i32),
+                        Some(deletions.unwrap_or(0) as i32),
+                        (files_changed < COMMIT_FILES_LIMIT).then_some(files_changed as i32),
+                    )
+                }
+                // The commit is gone; record it as empty rather than retrying
+                // forever
+                Err(AppError::GitHub(octocrab::Error::GitHub { source, .. }))
+                    if source.status_code == StatusCode::NOT_FOUND =>
+                {
+                    (Some(0), Some(0), Some(0))
+                }
+                // The diff is too large for GitHub to compute, so the stats
+                // stay unknown
+                Err(AppError::GitHub(octocrab::Error::GitHub { source, .. }))
+                    if source.status_code == StatusCode::UNPROCESSABLE_ENTITY =>
+                {
+                    (None, None, None)
+                }
+                Err(e) => return Err(e),
+            };
 
             sqlx::query!(
-                "INSERT INTO commit (repository_id, sha, message, author,
-                date) VALUES ($1, $2, $3, $4, $5) ON CONFLICT (repository_id, sha)
-                DO NOTHING",
+                r#"
+                UPDATE commit
+                SET additions = $3, deletions = $4, files_changed = $5, stats_fetched = true
+                WHERE repository_id = $1 AND sha = $2
+                "#,
                 job.repository_id,
-                commit.sha,
-                commit.commit.message,
-                author,
-                date
+                sha,
+                stats.0,
+                stats.1,
+                stats.2
             )
             .execute(pool)
             .await?;
         }
+        stats_fetched += shas.len();
 
-        if commits.next.is_none() || !new_commits_found {
-            break;
+        if is_cancel_requested(pool, job.job_id).await? {
+            info!(
+                "Stats backfill for repository {}/{} cancelled after {} commits",
+                job.owner, job.name, stats_fetched
+            );
+            return Ok(SyncOutcome::Cancelled);
         }
-        page += 1;
     }
+}
 
-    if !new_commits_found {
-        info!(
-            "No new commits found for repository: {}/{}",
-            job.owner, job.name
-        );
+/// Walks one branch from `head_sha` back to the head seen on its previous
+/// sync, or back to `base_sha` for a branch that has not been synced yet.
+/// The stored head only moves once the walk has finished, so a cancelled or
+/// failed sync resumes from the old head.
+async fn sync_branch(
+    job: &Job,
+    octocrab: &Octocrab,
+    pool: &PgPool,
+    branch: &str,
+    head_sha: &str,
+    base_sha: Option<&str>,
+) -> Result<SyncOutcome, AppError> {
+    let known_head_sha = get_branch_head(pool, job.repository_id, branch).await?;
+    info!(
+        "Branch {} of repository {}/{} is at {}, last synced at {:?}",
+        branch, job.owner, job.name, head_sha, known_head_sha
+    );
+
+    let base_sha = match job.mode {
+        SyncMode::Full => base_sha.map(str::to_string),
+        SyncMode::Incremental => known_head_sha.or_else(|| base_sha.map(str::to_string)),
+    };
+
+    let outcome = match base_sha {
+        Some(base_sha) if base_sha == head_sha => {
+            info!(
+                "No new commits found on branch {} of repository: {}/{}",
+                branch, job.owner, job.name
+            );
+            SyncOutcome::Completed
+        }
+        Some(base_sha) => {
+            match walk_comparison(job, octocrab, pool, branch, &base_sha, head_sha).await {
+                // The old head no longer exists, e.g. after a force-push
+                Err(AppError::GitHub(octocrab::Error::GitHub { source, .. }))
+                    if source.status_code == StatusCode::NOT_FOUND =>
+                {
+                    info!(
+                        "Base {} of branch {} of repository {}/{} is gone, walking full history",
+                        base_sha, branch, job.owner, job.name
+                    );
+                    walk_history(job, octocrab, pool, branch, head_sha).await?
+                }
+                result => result?,
+            }
+        }
+        None => walk_history(job, octocrab, pool, branch, head_sha).await?,
+    };
+
+    if let SyncOutcome::Cancelled = outcome {
+        return Ok(outcome);
     }
 
     sqlx::query!(
-        "UPDATE repository SET indexed_at = $1 WHERE repository_id = $2",
-        Utc::now(),
-        job.repository_id
+        r#"
+        INSERT INTO repository_branch (repository_id, branch, head_sha)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (repository_id, branch) DO UPDATE
+        SET head_sha = EXCLUDED.head_sha, synced_at = NOW()
+        "#,
+        job.repository_id,
+        branch,
+        head_sha
     )
     .execute(pool)
     .await?;
 
-    info!(
-        "Updated indexed_at for repository {}/{} to {}",
-        job.owner,
-        job.name,
-        Utc::now()
+    Ok(SyncOutcome::Completed)
+}
+
+/// Fetches every commit reachable from `head_sha`. Used for the first sync
+/// of a branch and whenever the previously seen head has disappeared.
+async fn walk_history(
+    job: &Job,
+    octocrab: &Octocrab,
+    pool: &PgPool,
+    branch: &str,
+    head_sha: &str,
+) -> Result<SyncOutcome, AppError> {
+    let mut page: u32 = 1;
+
+    loop {
+        let commits = with_backoff(|| async {
+            octocrab
+                .repos(&job.owner, &job.name)
+                .list_commits()
+                .sha(head_sha)
+                .page(page)
+                .per_page(100)
+                .send()
+                .await
+        })
+        .await?;
+        let has_next = commits.next.is_some();
+        let commits_seen = commits.items.len() as i32;
+
+        let commits_inserted =
+            insert_commits(pool, job.repository_id, branch, commits.items).await?;
+        let cancel_requested =
+            record_page_progress(pool, job.job_id, commits_seen, commits_inserted).await?;
+
+        if !has_next {
+            return Ok(SyncOutcome::Completed);
+        }
+        if cancel_requested {
+            info!(
+                "Sync for repository {}/{} cancelled after {} pages",
+                job.owner, job.name, page
+            );
+            return Ok(SyncOutcome::Cancelled);
+        }
+        page += 1;
+    }
+}
+
+/// Fetches the commits reachable from `head_sha` but not from `base_sha`, which includes commits from merged branches and
+/// rewritten history regardless of their author dates.
+async fn walk_comparison(
+    job: &Job,
+    octocrab: &Octocrab,
+    pool: &PgPool,
+    branch: &str,
+    base_sha: &str,
+    head_sha: &str,
+) -> Result<SyncOutcome, AppError> {
+    let route = format!(
+        "/repos/{}/{}/compare/{}...{}",
+        job.owner, job.name, base_sha, head_sha
     );
+    let mut page: u32 = 1;
+    let mut commits_seen: usize = 0;
+
+    loop {
+        let comparison: CommitComparison = with_backoff(|| async {
+            octocrab
+                .get(&route, Some(&[("page", page), ("per_page", 100)]))
+                .await
+        })
+        .await?;
+        let total_commits = comparison.total_commits;
+        let page_len = comparison.commits.len();
+        commits_seen += page_len;
 
-    Ok(())
+        let commits_inserted =
+            insert_commits(pool, job.repository_id, branch, comparison.commits).await?;
+        let cancel_requested =
+            record_page_progress(pool, job.job_id, page_len as i32, commits_inserted).await?;
+
+        if page_len == 0 || commits_seen >= total_commits {
+            return Ok(SyncOutcome::Completed);
+        }
+        if cancel_requested {
+            info!(
+                "Sync for repository {}/{} cancelled after {} pages",
+                job.owner, job.name, page
+            );
+            return Ok(SyncOutcome::Cancelled);
+        }
+        page += 1;
+    }
 }
 
-async fn get_latest_commit_date(
+/// Inserts the commits, skipping ones that are already stored, records that
+/// they are on `branch` and returns how many were new.
+async fn insert_commits(
     pool: &PgPool,
     repository_id: i32,
-) -> Result<Option<DateTime<Utc>>, AppError> {
-    let result = sqlx::query!(
-        "SELECT MAX(date) as latest_date FROM commit WHERE repository_id = $1",
-        repository_id
+    branch: &str,
+    commits: Vec<RepoCommit>,
+) -> Result<i32, AppError> {
+    let mut commits_inserted: i32 = 0;
+
+    for commit in commits {
+        let git_author = commit.commit.author.as_ref();
+        let git_committer = commit.commit.committer.as_ref();
+        let author = git_author
+            .map(|a| a.name.clone())
+            .unwrap_or_else(|| "Unknown".to_string());
+        let date = git_author.and_then(|a| a.date).unwrap_or_else(Utc::now);
+        let author_email = git_author
+            .map(|a| a.email.as_str())
+            .filter(|email| !email.is_empty());
+        let author_login = commit.author.as_ref().map(|a| a.login.as_str());
+        let author_github_id = commit.author.as_ref().map(|a| a.id.0 as i64);
+        let is_github_bot = commit.author.as_ref().is_some_and(is_bot_account);
+
+        // Commits stored before identities were recorded get them filled in
+        // when they are seen again, e.g. by a full sync. Authors are also
+        // flagged as bots when they match one of the global bot patterns.
+        let inserted = sqlx::query_scalar!(
+            r#"
+            INSERT INTO commit (repository_id, sha, message, author, date,
+            author_email, author_login, author_github_id,
+            committer_name, committer_email, committer_login, committer_github_id,
+            is_bot)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12,
+                $13 OR EXISTS (
+                    SELECT 1 FROM bot_pattern b
+                    WHERE b.collection_id IS NULL
+                    AND ($4 ~* b.pattern OR $7 ~* b.pattern OR $6 ~* b.pattern)
+                ))
+            ON CONFLICT (repository_id, sha) DO UPDATE
+            SET author_email = EXCLUDED.author_email,
+                author_login = EXCLUDED.author_login,
+                author_github_id = EXCLUDED.author_github_id,
+                committer_name = EXCLUDED.committer_name,
+                committer_email = EXCLUDED.committer_email,
+                committer_login = EXCLUDED.committer_login,
+                committer_github_id = EXCLUDED.committer_github_id,
+                is_bot = EXCLUDED.is_bot
+            WHERE commit.author_email IS NULL AND commit.author_github_id IS NULL
+            RETURNING (xmax = 0) AS "inserted!"
+            "#,
+            repository_id,
+            commit.sha,
+            commit.commit.message,
+            author,
+            date,
+            author_email,
+            author_login,
+            author_github_id,
+            git_committer.map(|c| c.name.as_str()),
+            git_committer.map(|c| c.email.as_str()),
+            commit.committer.as_ref().map(|c| c.login.as_str()),
+            commit.committer.as_ref().map(|c| c.id.0 as i64),
+            is_github_bot
+        )
+        .fetch_optional(pool)
+        .await?;
+        if inserted == Some(true) {
+            commits_inserted += 1;
+        }
+        if inserted.is_some() {
+            let aliases = commit_aliases(author_github_id, author_email, &author);
+            link_contributor(pool, &aliases, author_login.unwrap_or(&author)).await?;
+        }
+
+        sqlx::query!(
+            r#"
+            INSERT INTO commit_branch (commit_id, branch)
+            SELECT commit_id, $3 FROM commit
+            WHERE repository_id = $1 AND sha = $2
+            ON CONFLICT DO NOTHING
+            "#,
+            repository_id,
+            commit.sha,
+            branch
+        )
+        .execute(pool)
+        .await?;
+    }
+
+    Ok(commits_inserted)
+}
+
+/// Returns whether the job has been asked to cancel.
+async fn record_page_progress(
+    pool: &PgPool,
+    job_id: i32,
+    commits_seen: i32,
+    commits_inserted: i32,
+) -> Result<bool, AppError> {
+    let row = sqlx::query!(
+        "UPDATE sync_job SET pages_fetched = pages_fetched + 1,
+        commits_seen = commits_seen + $2, commits_inserted = commits_inserted + $3
+        WHERE sync_job_id = $1 RETURNING cancel_requested",
+        job_id,
+        commits_seen,
+        commits_inserted
     )
     .fetch_one(pool)
     .await?;
 
-    Ok(result.latest_date)
+    Ok(row.cancel_requested)
+}
+
+async fn get_branch_head(
+    pool: &PgPool,
+    repository_id: i32,
+    branch: &str,
+) -> Result<Option<String>, AppError> {
+    let head_sha = sqlx::query_scalar!(
+        "SELECT head_sha FROM repository_branch WHERE repository_id = $1 AND branch = $2",
+        repository_id,
+        branch
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(head_sha)
 }
 
-async fn fetch_commits_with_backoff(
+async fn fetch_default_branch(
     octocrab: &Octocrab,
     owner: &str,
     repo: &str,
-    page: u32,
-) -> Result<Page<RepoCommit>, AppError> {
-    let operation = || async {
-        octocrab
-            .repos(owner, repo)
-            .list_commits()
-            .page(page)
-            .per_page(100)
-            .send()
-            .await
-            .map_err(|e| {
-                if let octocrab::Error::GitHub { source, .. } = &e {
-                    if source.message.contains("API rate limit exceeded") {
-                        BackoffError::transient(e)
-                    } else {
-                        BackoffError::permanent(e)
-                    }
-                } else {
-                    BackoffError::permanent(e)
-                }
-            })
-    };
+) -> Result<String, AppError> {
+    let repository = with_backoff(|| async { octocrab.repos(owner, repo).get().await }).await?;
+
+    Ok(repository
+        .default_branch
+        .unwrap_or_else(|| "main".to_string()))
+}
+
+/// Lists every branch of the repository with its head commit.
+async fn fetch_branches(
+    octocrab: &Octocrab,
+    owner: &str,
+    repo: &str,
+) -> Result<Vec<(String, String)>, AppError> {
+    let mut branches = Vec::new();
+    let mut page: u32 = 1;
+
+    loop {
+        let branch_page = with_backoff(|| async {
+            octocrab
+                .repos(owner, repo)
+                .list_branches()
+                .page(page)
+                .per_page(100)
+                .send()
+                .await
+        })
+        .await?;
+        let has_next = branch_page.next.is_some();
+
+        branches.extend(
+            branch_page
+                .items
+                .into_iter()
+                .map(|branch| (branch.name, branch.commit.sha)),
+        );
+
+        if !has_next {
+            return Ok(branches);
+        }
+        page += 1;
+    }
+}
+
+async fn get_branch_patterns(pool: &PgPool, repository_id: i32) -> Result<Vec<Pattern>, AppError> {
+    let patterns = sqlx::query_scalar!(
+        "SELECT branch_patterns FROM repository WHERE repository_id = $1",
+        repository_id
+    )
+    .fetch_one(pool)
+    .await?;
 
-    backoff::future::retry(ExponentialBackoff::default(), operation)
-        .await
-        .map_err(AppError::from)
+    // Patterns are validated when they are saved
+    Ok(patterns
+        .iter()
+        .filter_map(|pattern| Pattern::new(pattern).ok())
+        .collect())
 }
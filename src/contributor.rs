@@ -0,0 +1,560 @@
+use crate::auth_utils::get_account_id;
+use crate::error::AppError;
+use crate::types::PaginatedResponse;
+use crate::AppState;
+use actix_web::{web, HttpRequest, HttpResponse};
+use chrono::{DateTime, Utc};
+use octocrab::models::Author;
+use serde::{Deserialize, Serialize};
+use sqlx::{PgPool, Postgres, Transaction};
+
+#[derive(Debug, Serialize)]
+pub struct Contributor {
+    contributor_id: i32,
+    display_name: String,
+    /// True once the identity has been merged or split by hand.
+    locked: bool,
+    /// Identities resolving to this contributor, in the same form as
+    /// `commit.contributor_key`.
+    aliases: Vec<String>,
+    commit_count: i64,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+}
+
+/// Whether GitHub reports the account as a bot or it uses the `[bot]`
+/// suffix GitHub Apps commit under.
+pub fn is_bot_account(author: &Author) -> bool {
+    author.r#type == "Bot" || author.login.ends_with("[bot]")
+}
+
+/// Returns the aliases a commit's author is known by. The first one is
+/// always the commit's `contributor_key`.
+pub fn commit_aliases(github_id: Option<i64>, email: Option<&str>, name: &str) -> Vec<String> {
+    let mut aliases = Vec::new();
+    if let Some(github_id) = github_id {
+        aliases.push(format!("github:{}", github_id));
+    }
+    if let Some(email) = email {
+        let email = email.to_lowercase();
+        // GitHub's private addresses look like 12345+login@users.noreply.github.com
+        if github_id.is_none() {
+            if let Some(id) = email
+                .strip_suffix("@users.noreply.github.com")
+                .and_then(|local| local.split_once('+'))
+                .and_then(|(id, _)| id.parse::<i64>().ok())
+            {
+                aliases.push(format!("email:{}", email));
+                aliases.push(format!("github:{}", id));
+                return aliases;
+            }
+        }
+        aliases.push(format!("email:{}", email));
+    }
+    if aliases.is_empty() {
+        aliases.push(format!("name:{}", name));
+    }
+    aliases
+}
+
+/// Attaches the aliases seen together on a commit to one contributor, in
+/// the spirit of `.mailmap`: aliases nobody claims yet join the contributor
+/// that owns the others, and contributors the aliases show to be the same
+/// person are merged unless one of them has been curated by hand.
+///
+/// GitHub accounts are never joined this way. Emails like `root@localhost`
+/// are shared by unrelated people, so a contributor tied to one account
+/// does not take on another account or merge with its contributor; the
+/// shared alias stays where it is.
+pub async fn link_contributor(
+    pool: &PgPool,
+    aliases: &[String],
+    display_name: &str,
+) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    let known = sqlx::query!(
+        r#"
+        SELECT a.alias, c.contributor_id, c.locked,
+            ARRAY(
+                SELECT g.alias FROM contributor_alias g
+                WHERE g.contributor_id = c.contributor_id AND g.alias LIKE 'github:%'
+            ) AS "github_aliases!"
+        FROM contributor_alias a
+        JOIN contributor c ON c.contributor_id = a.contributor_id
+        WHERE a.alias = ANY($1)
+        ORDER BY c.contributor_id
+        "#,
+        aliases
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+
+    let mut owners: Vec<(i32, bool, &[String])> = known
+        .iter()
+        .map(|row| {
+            (
+                row.contributor_id,
+                row.locked,
+                row.github_aliases.as_slice(),
+            )
+        })
+        .collect();
+    owners.dedup_by_key(|(contributor_id, _, _)| *contributor_id);
+    if known.len() == aliases.len() && owners.len() == 1 {
+        return tx.commit().await;
+    }
+
+    // The GitHub account these aliases belong to, if any is known
+    let account = aliases
+        .iter()
+        .find(|alias| alias.starts_with("github:"))
+        .or_else(|| owners.iter().find_map(|(_, _, github)| github.first()));
+    owners.retain(|(_, _, github)| {
+        github.is_empty() || account.is_some_and(|account| github.contains(account))
+    });
+
+    let target = owners
+        .iter()
+        .position(|(_, _, github)| !github.is_empty())
+        .unwrap_or(0);
+    let contributor_id = match owners.get(target) {
+        Some((contributor_id, _, _)) => *contributor_id,
+        None => {
+            sqlx::query_scalar!(
+                "INSERT INTO contributor (display_name) VALUES ($1) RETURNING contributor_id",
+                display_name
+            )
+            .fetch_one(&mut *tx)
+            .await?
+        }
+    };
+
+    let result = sqlx::query!(
+        r#"
+        INSERT INTO contributor_alias (alias, contributor_id)
+        SELECT alias, $2 FROM UNNEST($1::text[]) AS alias
+        ON CONFLICT (alias) DO NOTHING
+        "#,
+        aliases,
+        contributor_id
+    )
+    .execute(&mut *tx)
+    .await?;
+    if owners.is_empty() && result.rows_affected() == 0 {
+        // The aliases are all claimed, by another worker or by contributors
+        // tied to other accounts
+        return tx.rollback().await;
+    }
+
+    if owners.iter().all(|(_, locked, _)| !locked) {
+        let duplicates: Vec<i32> = owners
+            .iter()
+            .map(|(contributor_id, _, _)| *contributor_id)
+            .filter(|id| *id != contributor_id)
+            .collect();
+        if !duplicates.is_empty() {
+            merge_into(&mut tx, contributor_id, &duplicates).await?;
+        }
+    }
+
+    tx.commit().await
+}
+
+/// Links a GitHub account seen on a pull request, review, issue or comment,
+/// where the account id is the only alias.
+pub async fn link_github_account(pool: &PgPool, account: &Author) -> Result<(), sqlx::Error> {
+    let aliases = [format!("github:{}", account.id.0)];
+    link_contributor(pool, &aliases, &account.login).await
+}
+
+/// Merging or splitting contributors changes every report they appear in,
+/// so it is only allowed when all of their activity is in repositories of
+/// collections the caller owns.
+async fn check_contributors_owner(
+    tx: &mut Transaction<'_, Postgres>,
+    req: &HttpRequest,
+    contributor_ids: &[i32],
+) -> Result<(), AppError> {
+    let account_id = get_account_id(req)?;
+    let owned = sqlx::query_scalar!(
+        r#"
+        WITH alias AS (
+            SELECT alias FROM contributor_alias WHERE contributor_id = ANY($1)
+        ),
+        activity AS (
+            SELECT c.repository_id
+            FROM "commit" c JOIN alias a ON a.alias = c.contributor_key
+            UNION
+            SELECT pr.repository_id
+            FROM pull_request pr JOIN alias a ON a.alias = pr.contributor_key
+            UNION
+            SELECT pr.repository_id
+            FROM pull_request_review r
+            JOIN pull_request pr ON pr.pull_request_id = r.pull_request_id
+            JOIN alias a ON a.alias = r.contributor_key
+            UNION
+            SELECT pr.repository_id
+            FROM pull_request_review_comment rc
+            JOIN pull_request pr ON pr.pull_request_id = rc.pull_request_id
+            JOIN alias a ON a.alias = rc.contributor_key
+            UNION
+            SELECT i.repository_id
+            FROM issue i JOIN alias a ON a.alias = i.contributor_key
+            UNION
+            SELECT i.repository_id
+            FROM issue_comment ic
+            JOIN issue i ON i.issue_id = ic.issue_id
+            JOIN alias a ON a.alias = ic.contributor_key
+            UNION
+            SELECT i.repository_id
+            FROM issue_close cl
+            JOIN issue i ON i.issue_id = cl.issue_id
+            JOIN alias a ON a.alias = cl.contributor_key
+        )
+        SELECT NOT EXISTS (
+            SELECT 1 FROM activity t
+            WHERE NOT EXISTS (
+                SELECT 1 FROM collection_repository cr
+                JOIN collection col ON col.collection_id = cr.collection_id
+                WHERE cr.repository_id = t.repository_id AND col.owner_id = $2
+            )
+        ) AS "owned!"
+        "#,
+        contributor_ids,
+        account_id
+    )
+    .fetch_one(&mut **tx)
+    .await?;
+
+    if owned {
+        Ok(())
+    } else {
+        Err(AppError::Unauthorized(
+            "Contributor is active in repositories outside your collections".into(),
+        ))
+    }
+}
+
+async fn merge_into(
+    tx: &mut Transaction<'_, Postgres>,
+    contributor_id: i32,
+    source_ids: &[i32],
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "UPDATE contributor_alias SET contributor_id = $1 WHERE contributor_id = ANY($2)",
+        contributor_id,
+        source_ids
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    sqlx::query!(
+        "DELETE FROM contributor WHERE contributor_id = ANY($1) AND contributor_id <> $2",
+        source_ids,
+        contributor_id
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(())
+}
+
+async fn fetch_contributor(
+    pool: &PgPool,
+    contributor_id: i32,
+) -> Result<Option<Contributor>, sqlx::Error> {
+    sqlx::query_as!(
+        Contributor,
+        r#"
+        SELECT
+            ct.contributor_id,
+            ct.display_name,
+            ct.locked,
+            COALESCE(
+                (SELECT array_agg(a.alias ORDER BY a.alias) FROM contributor_alias a
+                WHERE a.contributor_id = ct.contributor_id),
+                '{}'
+            ) as "aliases!",
+            (
+                SELECT COUNT(*) FROM commit c
+                JOIN contributor_alias a ON a.alias = c.contributor_key
+                WHERE a.contributor_id = ct.contributor_id
+            ) as "commit_count!",
+            ct.created_at,
+            ct.updated_at
+        FROM contributor ct
+        WHERE ct.contributor_id = $1
+        "#,
+        contributor_id
+    )
+    .fetch_optional(pool)
+    .await
+}
+
+#[derive(Deserialize)]
+pub struct ContributorListQuery {
+    page: Option<i64>,
+    page_size: Option<i64>,
+    /// Matches display names and aliases, case-insensitively.
+    search: Option<String>,
+}
+
+pub async fn list_contributors(
+    state: web::Data<AppState>,
+    query: web::Query<ContributorListQuery>,
+) -> Result<HttpResponse, AppError> {
+    let page = query.page.unwrap_or(1);
+    let page_size = query.page_size.unwrap_or(10);
+    let offset = (page - 1) * page_size;
+    let search = query.search.as_deref().map(|s| format!("%{}%", s));
+
+    let total = sqlx::query_scalar!(
+        r#"
+        SELECT COUNT(*) FROM contributor ct
+        WHERE $1::text IS NULL
+        OR ct.display_name ILIKE $1
+        OR EXISTS (
+            SELECT 1 FROM contributor_alias a
+            WHERE a.contributor_id = ct.contributor_id AND a.alias ILIKE $1
+        )
+        "#,
+        search
+    )
+    .fetch_one(&state.db_pool)
+    .await?
+    .unwrap_or(0);
+
+    let contributors = sqlx::query_as!(
+        Contributor,
+        r#"
+        SELECT
+            ct.contributor_id,
+            ct.display_name,
+            ct.locked,
+            COALESCE(
+                (SELECT array_agg(a.alias ORDER BY a.alias) FROM contributor_alias a
+                WHERE a.contributor_id = ct.contributor_id),
+                '{}'
+            ) as "aliases!",
+            (
+                SELECT COUNT(*) FROM commit c
+                JOIN contributor_alias a ON a.alias = c.contributor_key
+                WHERE a.contributor_id = ct.contributor_id
+            ) as "commit_count!",
+            ct.created_at,
+            ct.updated_at
+        FROM contributor ct
+        WHERE $1::text IS NULL
+        OR ct.display_name ILIKE $1
+        OR EXISTS (
+            SELECT 1 FROM contributor_alias a
+            WHERE a.contributor_id = ct.contributor_id AND a.alias ILIKE $1
+        )
+        ORDER BY ct.display_name, ct.contributor_id
+        LIMIT $2 OFFSET $3
+        "#,
+        search,
+        page_size,
+        offset
+    )
+    .fetch_all(&state.db_pool)
+    .await?;
+
+    let total_pages = (total as f64 / page_size as f64).ceil() as i64;
+    Ok(HttpResponse::Ok().json(PaginatedResponse {
+        data: contributors,
+        total,
+        page,
+        page_size,
+        total_pages,
+    }))
+}
+
+pub async fn get_contributor(
+    state: web::Data<AppState>,
+    contributor_id: web::Path<i32>,
+) -> Result<HttpResponse, AppError> {
+    match fetch_contributor(&state.db_pool, contributor_id.into_inner()).await? {
+        Some(contributor) => Ok(HttpResponse::Ok().json(contributor)),
+        None => Err(AppError::NotFound("Contributor not found".into())),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct MergeContributors {
+    /// Contributors folded into the one in the path; they are deleted.
+    contributor_ids: Vec<i32>,
+}
+
+pub async fn merge_contributors(
+    state: web::Data<AppState>,
+    req: HttpRequest,
+    contributor_id: web::Path<i32>,
+    body: web::Json<MergeContributors>,
+) -> Result<HttpResponse, AppError> {
+    let contributor_id = contributor_id.into_inner();
+    if body.contributor_ids.contains(&contributor_id) {
+        return Err(AppError::BadRequest(
+            "Cannot merge a contributor into itself".into(),
+        ));
+    }
+
+    let mut tx = state.db_pool.begin().await?;
+
+    let found = sqlx::query_scalar!(
+        "SELECT COUNT(*) FROM contributor WHERE contributor_id = $1 OR contributor_id = ANY($2)",
+        contributor_id,
+        &body.contributor_ids
+    )
+    .fetch_one(&mut *tx)
+    .await?
+    .unwrap_or(0);
+    if found != body.contributor_ids.len() as i64 + 1 {
+        return Err(AppError::NotFound("Contributor not found".into()));
+    }
+    let mut contributor_ids = body.contributor_ids.clone();
+    contributor_ids.push(contributor_id);
+    check_contributors_owner(&mut tx, &req, &contributor_ids).await?;
+
+    merge_into(&mut tx, contributor_id, &body.contributor_ids).await?;
+    sqlx::query!(
+        "UPDATE contributor SET locked = true, updated_at = NOW() WHERE contributor_id = $1",
+        contributor_id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    match fetch_contributor(&state.db_pool, contributor_id).await? {
+        Some(contributor) => Ok(HttpResponse::Ok().json(contributor)),
+        None => Err(AppError::NotFound("Contributor not found".into())),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct SplitContributor {
+    /// Aliases moved to a new contributor.
+    aliases: Vec<String>,
+    display_name: Option<String>,
+}
+
+/// Moves some of a contributor's aliases to a new contributor. Both are
+/// locked so the automatic merger does not join them again.
+pub async fn split_contributor(
+    state: web::Data<AppState>,
+    req: HttpRequest,
+    contributor_id: web::Path<i32>,
+    body: web::Json<SplitContributor>,
+) -> Result<HttpResponse, AppError> {
+    let contributor_id = contributor_id.into_inner();
+    let body = body.into_inner();
+    if body.aliases.is_empty() {
+        return Err(AppError::BadRequest("aliases must not be empty".into()));
+    }
+
+    let mut tx = state.db_pool.begin().await?;
+
+    let remaining = sqlx::query!(
+        r#"
+        SELECT
+            COUNT(*) FILTER (WHERE alias = ANY($2)) as "moved!",
+            COUNT(*) FILTER (WHERE NOT alias = ANY($2)) as "kept!"
+        FROM contributor_alias
+        WHERE contributor_id = $1
+        "#,
+        contributor_id,
+        &body.aliases
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    if remaining.moved != body.aliases.len() as i64 {
+        return Err(AppError::BadRequest(
+            "Every alias must belong to the contributor".into(),
+        ));
+    }
+    if remaining.kept == 0 {
+        return Err(AppError::BadRequest(
+            "A contributor must keep at least one alias".into(),
+        ));
+    }
+    check_contributors_owner(&mut tx, &req, &[contributor_id]).await?;
+
+    let display_name = body.display_name.unwrap_or_else(|| {
+        body.aliases[0]
+            .split_once(':')
+            .map(|(_, value)| value.to_string())
+            .unwrap_or_else(|| body.aliases[0].clone())
+    });
+    let new_contributor_id = sqlx::query_scalar!(
+        r#"
+        INSERT INTO contributor (display_name, locked)
+        VALUES ($1, true)
+        RETURNING contributor_id
+        "#,
+        display_name
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    sqlx::query!(
+        "UPDATE contributor_alias SET contributor_id = $1 WHERE alias = ANY($2)",
+        new_contributor_id,
+        &body.aliases
+    )
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query!(
+        "UPDATE contributor SET locked = true, updated_at = NOW() WHERE contributor_id = $1",
+        contributor_id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    match fetch_contributor(&state.db_pool, new_contributor_id).await? {
+        Some(contributor) => Ok(HttpResponse::Created().json(contributor)),
+        None => Err(AppError::NotFound("Contributor not found".into())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn puts_the_github_account_first() {
+        assert_eq!(
+            commit_aliases(Some(42), Some("Jane@Example.com"), "Jane"),
+            ["github:42", "email:jane@example.com"]
+        );
+        assert_eq!(
+            commit_aliases(Some(42), Some("7+jane@users.noreply.github.com"), "Jane"),
+            ["github:42", "email:7+jane@users.noreply.github.com"]
+        );
+    }
+
+    #[test]
+    fn reads_the_account_from_noreply_addresses() {
+        assert_eq!(
+            commit_aliases(None, Some("12345+Jane@users.noreply.github.com"), "Jane"),
+            ["email:12345+jane@users.noreply.github.com", "github:12345"]
+        );
+        // Older noreply addresses carry only the login
+        assert_eq!(
+            commit_aliases(None, Some("jane@users.noreply.github.com"), "Jane"),
+            ["email:jane@users.noreply.github.com"]
+        );
+        assert_eq!(
+            commit_aliases(None, Some("x+jane@users.noreply.github.com"), "Jane"),
+            ["email:x+jane@users.noreply.github.com"]
+        );
+    }
+
+    #[test]
+    fn falls_back_to_the_author_name() {
+        assert_eq!(commit_aliases(None, None, "Jane Doe"), ["name:Jane Doe"]);
+    }
+}
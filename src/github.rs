@@ -1,11 +1,13 @@
 use crate::error::AppError;
 use crate::{auth::Claims, types::PaginatedResponse};
 use actix_web::{web, HttpMessage, HttpRequest, HttpResponse};
+use backoff::{Error as BackoffError, ExponentialBackoff};
 use http::Uri;
 use log::error;
 use octocrab::params::repos::Type;
 use octocrab::Octocrab;
 use serde::{Deserialize, Serialize};
+use std::future::Future;
 
 pub fn get_github_token(req: &HttpRequest) -> Result<String, AppError> {
     let claims = req.extensions().get::<Claims>().cloned();
@@ -218,3 +220,29 @@ fn calculate_total_pages(page: u8, last_url: Option<&Uri>, prev_url: Option<&Uri
         1
     }
 }
+
+/// Retries a GitHub request with exponential backoff while it is being
+/// rate limited.
+pub async fn with_backoff<T, F, Fut>(operation: F) -> Result<T, AppError>
+where
+    F: Fn() -> Fut,
+    Fut: Future<Output = Result<T, octocrab::Error>>,
+{
+    let operation = || async {
+        operation().await.map_err(|e| {
+            if let octocrab::Error::GitHub { source, .. } = &e {
+                if source.message.contains("API rate limit exceeded") {
+                    BackoffError::transient(e)
+                } else {
+                    BackoffError::permanent(e)
+                }
+            } else {
+                BackoffError::permanent(e)
+            }
+        })
+    };
+
+    backoff::future::retry(ExponentialBackoff::default(), operation)
+        .await
+        .map_err(AppError::from)
+}
@@ -1,12 +1,362 @@
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, Datelike, Duration, NaiveDate, TimeZone, Utc};
 use serde::{Deserialize, Serialize};
 use sqlx::PgPool;
 
-fn growth_accounting_query(dau: String, query: String) -> String {
+/// The activity that makes a contributor active (the "MAU" side of growth
+/// accounting).
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ActivitySource {
+    #[default]
+    Commits,
+    /// Opening a pull request.
+    PullRequests,
+    /// Submitting a review or leaving a review comment.
+    Reviews,
+    /// Opening, commenting on or closing an issue.
+    Issues,
+    /// Any code contribution: commits, pull requests or reviews.
+    Any,
+}
+
+/// What a contributor's `inc_amt` measures on the "revenue" side of growth
+/// accounting (MRR and LTV). Without one, the source's events are counted.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Metric {
+    Commits,
+    /// Days with at least one event from the source.
+    ActiveDays,
+    LinesAdded,
+    /// Lines added plus lines deleted.
+    LinesChanged,
+    /// Pull requests merged, credited to their authors on the day of the
+    /// merge.
+    PrsMerged,
+}
+
+/// Options shared by the repository and collection growth accounting
+/// endpoints.
+#[derive(Debug, Default, Deserialize)]
+pub struct GrowthAccountingParams {
+    /// Leave out activity by bots and automation accounts.
+    #[serde(default)]
+    pub exclude_bots: bool,
+    #[serde(default)]
+    pub source: ActivitySource,
+    pub metric: Option<Metric>,
+    #[serde(default)]
+    pub granularity: Granularity,
+    /// Only report periods from the one with this date on.
+    pub from: Option<NaiveDate>,
+    /// Only report periods up to and including the one with this date.
+    pub to: Option<NaiveDate>,
+    /// Only report the periods in a trailing window ending at `to`, or now.
+    pub window: Option<TrailingWindow>,
+}
+
+impl GrowthAccountingParams {
+    pub fn validate(&self) -> Result<(), &'static str> {
+        if self.from.is_some() && self.window.is_some() {
+            return Err("Pass either from or window, not both");
+        }
+        if let (Some(from), Some(to)) = (self.from, self.to) {
+            if from > to {
+                return Err("from must not be after to");
+            }
+        }
+        Ok(())
+    }
+
+    /// SQL condition keeping the reported periods, whose starts are in
+    /// `column`, within the requested range. It is applied to the results
+    /// rather than the activity, so the first periods in range are still
+    /// classified against everything before them.
+    fn range_condition(&self, column: &str) -> String {
+        self.range_condition_in(self.granularity, column)
+    }
+
+    /// Like `range_condition`, for periods other than the requested
+    /// granularity.
+    fn range_condition_in(&self, granularity: Granularity, column: &str) -> String {
+        let unit = granularity.unit();
+        let mut conditions = vec!["TRUE".to_string()];
+        if let Some(from) = self.from {
+            conditions.push(format!(
+                "{} >= date_trunc('{}', '{}'::timestamptz)",
+                column, unit, from
+            ));
+        }
+        if let Some(to) = self.to {
+            conditions.push(format!(
+                "{} <= date_trunc('{}', '{}'::timestamptz)",
+                column, unit, to
+            ));
+        }
+        if let Some(window) = &self.window {
+            let end = match self.to {
+                Some(to) => format!("'{}'::timestamptz", to),
+                None => "now()".to_string(),
+            };
+            conditions.push(format!(
+                "{} > date_trunc('{}', {} - interval '{}')",
+                column, unit, end, window.interval
+            ));
+        }
+        conditions.join(" AND ")
+    }
+}
+
+/// A trailing window written as a count and a unit, e.g. `12m`. The units
+/// are `d`, `w`, `m`, `q` and `y`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(try_from = "String")]
+pub struct TrailingWindow {
+    interval: String,
+}
+
+// Longer windows would take `to - window` out of Postgres's timestamp range,
+// and no repository's history comes close
+const MAX_WINDOW_YEARS: u32 = 1000;
+
+impl TryFrom<String> for TrailingWindow {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let invalid = || format!("invalid window {:?}, expected e.g. 12m", value);
+        let (split, _) = value.char_indices().last().ok_or_else(invalid)?;
+        let (count, unit) = value.split_at(split);
+        let count: u32 = count.parse().map_err(|_| invalid())?;
+        if count == 0 {
+            return Err(invalid());
+        }
+        let (amount, unit, max) = match unit {
+            "d" => (Some(count), "days", MAX_WINDOW_YEARS * 366),
+            "w" => (Some(count), "weeks", MAX_WINDOW_YEARS * 53),
+            "m" => (Some(count), "months", MAX_WINDOW_YEARS * 12),
+            "q" => (count.checked_mul(3), "months", MAX_WINDOW_YEARS * 12),
+            "y" => (Some(count), "years", MAX_WINDOW_YEARS),
+            _ => return Err(invalid()),
+        };
+        match amount {
+            Some(amount) if amount <= max => Ok(TrailingWindow {
+                interval: format!("{} {}", amount, unit),
+            }),
+            _ => Err(format!(
+                "window {:?} is too long, the maximum is {} years",
+                value, MAX_WINDOW_YEARS
+            )),
+        }
+    }
+}
+
+/// The length of the periods growth accounting compares. Results keep their
+/// `month` field names whatever the granularity.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Granularity {
+    Week,
+    #[default]
+    Month,
+    Quarter,
+}
+
+impl Granularity {
+    fn unit(&self) -> &'static str {
+        match self {
+            Granularity::Week => "week",
+            Granularity::Month => "month",
+            Granularity::Quarter => "quarter",
+        }
+    }
+
+    fn interval(&self) -> &'static str {
+        match self {
+            Granularity::Week => "1 week",
+            Granularity::Month => "1 month",
+            Granularity::Quarter => "3 months",
+        }
+    }
+
+    /// The start of the period `now` falls in, which has not ended yet.
+    fn period_start(&self, now: DateTime<Utc>) -> DateTime<Utc> {
+        let date = now.date_naive();
+        let start = match self {
+            Granularity::Week => {
+                date - Duration::days(date.weekday().num_days_from_monday() as i64)
+            }
+            Granularity::Month => date.with_day(1).unwrap_or(date),
+            Granularity::Quarter => {
+                NaiveDate::from_ymd_opt(date.year(), (date.month0() / 3) * 3 + 1, 1).unwrap_or(date)
+            }
+        };
+        Utc.from_utc_datetime(&start.and_hms_opt(0, 0, 0).unwrap_or_default())
+    }
+
+    /// SQL for the number of whole periods between two period starts.
+    fn periods_between(&self, from: &str, to: &str) -> String {
+        let months = format!(
+            "(12 * (extract(year FROM {to}) - extract(year FROM {from})) + extract(month FROM {to}) - extract(month FROM {from}))"
+        );
+        match self {
+            Granularity::Week => format!("round(extract(epoch FROM ({to} - {from})) / 604800)"),
+            Granularity::Month => months,
+            Granularity::Quarter => format!("({months} / 3)"),
+        }
+    }
+}
+
+/// The repositories a growth accounting report covers.
+#[derive(Debug, Clone, Copy)]
+pub enum DauScope {
+    Repository(i32),
+    Collection(i32),
+}
+
+// Every event query has the same columns so the DAU query can filter and
+// group them alike. The identity columns are what bot patterns match on.
+const COMMIT_EVENTS: &str = r#"
+            SELECT c.repository_id, c.contributor_key, c."date" AS ts, c.is_bot,
+                c.author, c.author_login, c.author_email, c.additions, c.deletions
+            FROM "commit" c"#;
+
+const PULL_REQUEST_EVENTS: &str = r#"
+            SELECT pr.repository_id, pr.contributor_key, pr.created_at AS ts, pr.is_bot,
+                NULL AS author, pr.author_login, NULL AS author_email,
+                NULL::integer AS additions, NULL::integer AS deletions
+            FROM pull_request pr"#;
+
+const MERGED_PULL_REQUEST_EVENTS: &str = r#"
+            SELECT pr.repository_id, pr.contributor_key, pr.merged_at AS ts, pr.is_bot,
+                NULL AS author, pr.author_login, NULL AS author_email,
+                NULL::integer AS additions, NULL::integer AS deletions
+            FROM pull_request pr
+            WHERE pr.merged_at IS NOT NULL"#;
+
+const REVIEW_EVENTS: &str = r#"
+            SELECT pr.repository_id, r.contributor_key, r.submitted_at AS ts, r.is_bot,
+                NULL AS author, r.reviewer_login AS author_login, NULL AS author_email,
+                NULL::integer AS additions, NULL::integer AS deletions
+            FROM pull_request_review r
+            JOIN pull_request pr ON pr.pull_request_id = r.pull_request_id
+            WHERE r.submitted_at IS NOT NULL
+            UNION ALL
+            SELECT pr.repository_id, rc.contributor_key, rc.created_at, rc.is_bot,
+                NULL, rc.author_login, NULL, NULL, NULL
+            FROM pull_request_review_comment rc
+            JOIN pull_request pr ON pr.pull_request_id = rc.pull_request_id"#;
+
+const ISSUE_EVENTS: &str = r#"
+            SELECT i.repository_id, i.contributor_key, i.created_at AS ts, i.is_bot,
+                NULL AS author, i.author_login, NULL AS author_email,
+                NULL::integer AS additions, NULL::integer AS deletions
+            FROM issue i
+            UNION ALL
+            SELECT i.repository_id, ic.contributor_key, ic.created_at, ic.is_bot,
+                NULL, ic.author_login, NULL, NULL, NULL
+            FROM issue_comment ic
+            JOIN issue i ON i.issue_id = ic.issue_id
+            UNION ALL
+            SELECT i.repository_id, cl.contributor_key, cl.created_at, cl.is_bot,
+                NULL, cl.actor_login, NULL, NULL, NULL
+            FROM issue_close cl
+            JOIN issue i ON i.issue_id = cl.issue_id"#;
+
+fn source_events(source: ActivitySource) -> String {
+    match source {
+        ActivitySource::Commits => COMMIT_EVENTS.to_string(),
+        ActivitySource::PullRequests => PULL_REQUEST_EVENTS.to_string(),
+        ActivitySource::Reviews => REVIEW_EVENTS.to_string(),
+        ActivitySource::Issues => ISSUE_EVENTS.to_string(),
+        ActivitySource::Any => {
+            [COMMIT_EVENTS, PULL_REQUEST_EVENTS, REVIEW_EVENTS].join("\n            UNION ALL")
+        }
+    }
+}
+
+/// Builds the DAU query the growth accounting CTEs run on: one row per
+/// contributor and day, with `metric` summed into `inc_amt`. Contributors
+/// resolve through their aliases, so merged identities count once.
+pub fn dau_query(
+    scope: DauScope,
+    params: &GrowthAccountingParams,
+    metric: Option<Metric>,
+) -> String {
+    let (events, inc_amt) = match metric {
+        None => (source_events(params.source), "count(*)"),
+        Some(Metric::ActiveDays) => (source_events(params.source), "1"),
+        Some(Metric::Commits) => (COMMIT_EVENTS.to_string(), "count(*)"),
+        // Stats are NULL until they have been fetched, and for diffs too
+        // large for GitHub to compute
+        Some(Metric::LinesAdded) => (COMMIT_EVENTS.to_string(), "COALESCE(sum(e.additions), 0)"),
+        Some(Metric::LinesChanged) => (
+            COMMIT_EVENTS.to_string(),
+            "COALESCE(sum(e.additions + e.deletions), 0)",
+        ),
+        Some(Metric::PrsMerged) => (MERGED_PULL_REQUEST_EVENTS.to_string(), "count(*)"),
+    };
+
+    let (join, filter) = match scope {
+        DauScope::Repository(repository_id) => (
+            String::new(),
+            format!("e.repository_id = {}", repository_id),
+        ),
+        DauScope::Collection(collection_id) => (
+            "JOIN collection_repository cr ON cr.repository_id = e.repository_id".to_string(),
+            format!("cr.collection_id = {}", collection_id),
+        ),
+    };
+
+    let mut bot_filter = String::new();
+    if params.exclude_bots {
+        bot_filter.push_str("AND NOT e.is_bot");
+        // On top of the bots flagged at ingestion, a collection's own
+        // patterns apply to all activity in it
+        if let DauScope::Collection(collection_id) = scope {
+            bot_filter.push_str(&format!(
+                r#"
+            AND NOT EXISTS (
+                SELECT 1 FROM bot_pattern b
+                WHERE b.collection_id = {}
+                AND (e.author ~* b.pattern OR e.author_login ~* b.pattern
+                    OR e.author_email ~* b.pattern)
+            )"#,
+                collection_id
+            ));
+        }
+    }
+
+    format!(
+        r#"
+        SELECT
+            COALESCE(ca.contributor_id::text, e.contributor_key) AS user_id,
+            date_trunc('day', e.ts) AS dt,
+            {} AS inc_amt
+        FROM ({}
+        ) e
+            {}
+            LEFT JOIN contributor_alias ca ON ca.alias = e.contributor_key
+        WHERE
+            {}
+            -- Activity by deleted GitHub accounts has no contributor
+            AND e.contributor_key IS NOT NULL
+            {}
+        GROUP BY
+            1,
+            2
+        "#,
+        inc_amt, events, join, filter, bot_filter
+    )
+}
+
+fn growth_accounting_query(dau: String, granularity: Granularity, query: String) -> String {
+    let unit = granularity.unit();
+    let interval = granularity.interval();
+    let periods_since_first = granularity.periods_between("first_month", "month");
     return format!(
         r#"
         WITH dau AS (
-            {}
+            {dau}
         ),
         -- First, set up WAU and MAU tables for future use
         wau AS (
@@ -21,9 +371,11 @@ fn growth_accounting_query(dau: String, query: String) -> String {
                 1,
                 2
         ),
+        -- "month" is the reporting period, which is a month unless another
+        -- granularity was asked for
         mau AS (
             SELECT
-                date_trunc('month',
+                date_trunc('{unit}',
                     dt) AS month,
                 user_id,
                 sum(inc_amt) AS inc_amt
@@ -42,7 +394,7 @@ fn growth_accounting_query(dau: String, query: String) -> String {
                 min(dt) AS first_dt,
                 date_trunc('week',
                     min(dt)) AS first_week,
-                date_trunc('month',
+                date_trunc('{unit}',
                     min(dt)) AS first_month
             FROM
                 dau
@@ -70,7 +422,7 @@ fn growth_accounting_query(dau: String, query: String) -> String {
         mau_growth_accounting AS (
             SELECT
                 coalesce(tm.month,
-                    lm.month + interval '1 month') AS month,
+                    lm.month + interval '{interval}') AS month,
                 count(DISTINCT tm.user_id) AS mau,
                 count(DISTINCT CASE WHEN lm.user_id IS NOT NULL THEN
                         tm.user_id
@@ -96,7 +448,7 @@ fn growth_accounting_query(dau: String, query: String) -> String {
             FROM
                 mau_decorated tm
             FULL OUTER JOIN mau_decorated lm ON (tm.user_id = lm.user_id
-                AND tm.month = lm.month + interval '1 month')
+                AND tm.month = lm.month + interval '{interval}')
         GROUP BY
             1
         ORDER BY
@@ -106,9 +458,7 @@ fn growth_accounting_query(dau: String, query: String) -> String {
         mau_retention_by_cohort AS (
             SELECT
                 first_month,
-                12 * extract(year FROM age(month,
-                        first_month)) + extract(month FROM age(month,
-                        first_month)) AS months_since_first,
+                {periods_since_first} AS months_since_first,
                 count(1) AS active_users,
                 sum(inc_amt) AS inc_amt
             FROM
@@ -127,7 +477,7 @@ fn growth_accounting_query(dau: String, query: String) -> String {
         mrr_growth_accounting AS (
             SELECT
                 coalesce(tm.month,
-                    lm.month + interval '1 month') AS month,
+                    lm.month + interval '{interval}') AS month,
                 sum(tm.inc_amt) AS rev,
                 sum(
                     CASE WHEN tm.user_id IS NOT NULL
@@ -188,7 +538,7 @@ fn growth_accounting_query(dau: String, query: String) -> String {
             FROM
                 mau_decorated tm
             FULL OUTER JOIN mau_decorated lm ON (tm.user_id = lm.user_id
-                AND tm.month = lm.month + interval '1 month')
+                AND tm.month = lm.month + interval '{interval}')
         GROUP BY
             1
         ORDER BY
@@ -274,7 +624,7 @@ fn growth_accounting_query(dau: String, query: String) -> String {
             SELECT
                 first_month,
                 month AS active_month,
-                extract(month FROM month) - extract(month FROM first_month) + 12 * (extract(year FROM month) - extract(year FROM first_month)) AS months_since_first,
+                {periods_since_first} AS months_since_first,
                 count(DISTINCT user_id) AS users,
                 sum(inc_amt) AS inc_amt
             FROM
@@ -328,10 +678,63 @@ fn growth_accounting_query(dau: String, query: String) -> String {
             ORDER BY
                 1,
                 2
+        ),
+        -- Stickiness compares the average daily and weekly active users in a
+        -- period with the users active at any time in it. Days and weeks
+        -- without activity count as zero, from the first day with activity
+        -- to the last.
+        calendar AS (
+            SELECT
+                generate_series(min(dt), max(dt), interval '1 day') AS dt
+            FROM
+                dau
+        ),
+        daily_users AS (
+            SELECT
+                c.dt,
+                count(DISTINCT d.user_id) AS users
+            FROM
+                calendar c
+            LEFT JOIN dau d ON d.dt = c.dt
+        GROUP BY
+            1
+        ),
+        weekly_users AS (
+            SELECT
+                date_trunc('week',
+                    c.dt) AS week,
+                count(DISTINCT d.user_id) AS users
+            FROM
+                calendar c
+            LEFT JOIN dau d ON d.dt = c.dt
+        GROUP BY
+            1
+        ),
+        stickiness AS (
+            SELECT
+                m.month,
+                (
+                    SELECT
+                        avg(users)
+                    FROM
+                        daily_users
+                    WHERE
+                        date_trunc('{unit}', dt) = m.month) AS dau,
+                (
+                    SELECT
+                        avg(users)
+                    FROM
+                        weekly_users
+                    WHERE
+                        date_trunc('{unit}', week) = m.month) AS wau,
+                count(DISTINCT m.user_id) AS mau
+            FROM
+                mau m
+            GROUP BY
+                1
         )
-        {}
-        "#,
-        dau, query
+        {query}
+        "#
     );
 }
 
@@ -343,13 +746,35 @@ pub struct MAUGrowthAccountingResult {
     new: i64,
     resurrected: i64,
     churned: i64,
+    /// (new + resurrected) / churned. The ratios are null when the
+    /// denominator is zero.
+    quick_ratio: Option<f64>,
+    /// The share of the previous period's contributors still active.
+    gross_retention: Option<f64>,
+    /// The share of the previous period's contributors who churned.
+    churn_rate: Option<f64>,
 }
 
 pub async fn mau_growth_accounting(
     pool: &PgPool,
     dau: String,
+    params: &GrowthAccountingParams,
 ) -> Result<Vec<MAUGrowthAccountingResult>, sqlx::Error> {
-    let q = growth_accounting_query(dau, "SELECT * FROM mau_growth_accounting".to_string());
+    let q = growth_accounting_query(
+        dau,
+        params.granularity,
+        format!(
+            "SELECT
+            *,
+            (new + resurrected)::DOUBLE PRECISION / NULLIF(-churned, 0) as quick_ratio,
+            retained::DOUBLE PRECISION / NULLIF(retained - churned, 0) as gross_retention,
+            (-churned)::DOUBLE PRECISION / NULLIF(retained - churned, 0) as churn_rate
+        FROM mau_growth_accounting
+        WHERE {}
+        ORDER BY month",
+            params.range_condition("month")
+        ),
+    );
 
     match sqlx::query_as::<_, MAUGrowthAccountingResult>(&q)
         .fetch_all(pool)
@@ -373,25 +798,51 @@ pub struct MRRGrowthAccountingResult {
     expansion: i64,
     churned: i64,
     contraction: i64,
+    /// (new + resurrected + expansion) / (churned + contraction).
+    quick_ratio: Option<f64>,
+    /// The share of the previous period's amount kept, leaving out expansion.
+    gross_retention: Option<f64>,
+    /// The share of the previous period's amount kept, including expansion.
+    net_retention: Option<f64>,
+    /// The share of the previous period's amount lost to churned contributors.
+    churn_rate: Option<f64>,
 }
 
 pub async fn mrr_growth_accounting(
     pool: &PgPool,
     dau: String,
+    params: &GrowthAccountingParams,
 ) -> Result<Vec<MRRGrowthAccountingResult>, sqlx::Error> {
     let q = growth_accounting_query(
         dau,
-        "SELECT
-            month,
-            COALESCE(rev::BIGINT, 0) as rev,
-            COALESCE(retained::BIGINT, 0) as retained,
-            COALESCE(new::BIGINT, 0) as new,
-            COALESCE(resurrected::BIGINT, 0) as resurrected,
-            COALESCE(expansion::BIGINT, 0) as expansion,
-            COALESCE(churned::BIGINT, 0) as churned,
-            COALESCE(contraction::BIGINT, 0) as contraction
-        FROM mrr_growth_accounting"
-            .to_string(),
+        params.granularity,
+        format!(
+            "SELECT
+            *,
+            (new + resurrected + expansion)::DOUBLE PRECISION
+                / NULLIF(-(churned + contraction), 0) as quick_ratio,
+            retained::DOUBLE PRECISION
+                / NULLIF(retained - churned - contraction, 0) as gross_retention,
+            (retained + expansion)::DOUBLE PRECISION
+                / NULLIF(retained - churned - contraction, 0) as net_retention,
+            (-churned)::DOUBLE PRECISION
+                / NULLIF(retained - churned - contraction, 0) as churn_rate
+        FROM (
+            SELECT
+                month,
+                COALESCE(rev::BIGINT, 0) as rev,
+                COALESCE(retained::BIGINT, 0) as retained,
+                COALESCE(new::BIGINT, 0) as new,
+                COALESCE(resurrected::BIGINT, 0) as resurrected,
+                COALESCE(expansion::BIGINT, 0) as expansion,
+                COALESCE(churned::BIGINT, 0) as churned,
+                COALESCE(contraction::BIGINT, 0) as contraction
+            FROM mrr_growth_accounting
+            WHERE {}
+        ) mrr
+        ORDER BY month",
+            params.range_condition("month")
+        ),
     );
 
     match sqlx::query_as::<_, MRRGrowthAccountingResult>(&q)
@@ -406,6 +857,103 @@ pub async fn mrr_growth_accounting(
     }
 }
 
+/// Averages of the growth accounting ratios over the last few periods that
+/// have ended, skipping periods where a ratio is undefined.
+#[derive(Serialize, Debug, Deserialize)]
+pub struct TrailingAverages {
+    periods: usize,
+    mau_quick_ratio: Option<f64>,
+    mau_gross_retention: Option<f64>,
+    mau_churn_rate: Option<f64>,
+    mrr_quick_ratio: Option<f64>,
+    mrr_gross_retention: Option<f64>,
+    mrr_net_retention: Option<f64>,
+    mrr_churn_rate: Option<f64>,
+}
+
+const TRAILING_PERIODS: [usize; 2] = [3, 12];
+
+pub fn summarize(
+    mau: &[MAUGrowthAccountingResult],
+    mrr: &[MRRGrowthAccountingResult],
+    granularity: Granularity,
+) -> Vec<TrailingAverages> {
+    let current = granularity.period_start(Utc::now());
+    let mau: Vec<_> = mau.iter().filter(|r| r.month < current).collect();
+    let mrr: Vec<_> = mrr.iter().filter(|r| r.month < current).collect();
+
+    TRAILING_PERIODS
+        .iter()
+        .map(|&periods| {
+            let mau = &mau[mau.len().saturating_sub(periods)..];
+            let mrr = &mrr[mrr.len().saturating_sub(periods)..];
+            TrailingAverages {
+                periods,
+                mau_quick_ratio: average(mau.iter().map(|r| r.quick_ratio)),
+                mau_gross_retention: average(mau.iter().map(|r| r.gross_retention)),
+                mau_churn_rate: average(mau.iter().map(|r| r.churn_rate)),
+                mrr_quick_ratio: average(mrr.iter().map(|r| r.quick_ratio)),
+                mrr_gross_retention: average(mrr.iter().map(|r| r.gross_retention)),
+                mrr_net_retention: average(mrr.iter().map(|r| r.net_retention)),
+                mrr_churn_rate: average(mrr.iter().map(|r| r.churn_rate)),
+            }
+        })
+        .collect()
+}
+
+fn average(values: impl Iterator<Item = Option<f64>>) -> Option<f64> {
+    let values: Vec<f64> = values.flatten().collect();
+    if values.is_empty() {
+        return None;
+    }
+    Some(values.iter().sum::<f64>() / values.len() as f64)
+}
+
+#[derive(sqlx::FromRow, Serialize, Debug, Deserialize)]
+pub struct StickinessResult {
+    month: DateTime<Utc>,
+    dau: f64,
+    wau: f64,
+    mau: i64,
+    dau_mau: f64,
+    wau_mau: f64,
+}
+
+pub async fn stickiness(
+    pool: &PgPool,
+    dau: String,
+    params: &GrowthAccountingParams,
+) -> Result<Vec<StickinessResult>, sqlx::Error> {
+    let q = growth_accounting_query(
+        dau,
+        params.granularity,
+        format!(
+            "SELECT
+            month,
+            COALESCE(dau, 0)::DOUBLE PRECISION as dau,
+            COALESCE(wau, 0)::DOUBLE PRECISION as wau,
+            mau::BIGINT,
+            (COALESCE(dau, 0) / mau)::DOUBLE PRECISION as dau_mau,
+            (COALESCE(wau, 0) / mau)::DOUBLE PRECISION as wau_mau
+        FROM stickiness
+        WHERE {}
+        ORDER BY month",
+            params.range_condition("month")
+        ),
+    );
+
+    match sqlx::query_as::<_, StickinessResult>(&q)
+        .fetch_all(pool)
+        .await
+    {
+        Ok(results) => Ok(results),
+        Err(e) => {
+            log::error!("Error fetching stickiness results: {}", e);
+            Err(e)
+        }
+    }
+}
+
 #[derive(sqlx::FromRow, Serialize, Debug, Deserialize)]
 pub struct MAURetentionByCohortResult {
     first_month: DateTime<Utc>,
@@ -414,32 +962,112 @@ pub struct MAURetentionByCohortResult {
     inc_amt: i64,
 }
 
-// pub async fn mau_retention_by_cohort(
-//     pool: &PgPool,
-//     dau: String,
-// ) -> Result<Vec<MAURetentionByCohortResult>, sqlx::Error> {
-//     let q = growth_accounting_query(
-//         dau,
-//         "SELECT
-//             first_month,
-//             months_since_first::BIGINT,
-//             active_users::BIGINT,
-//             inc_amt::BIGINT
-//         FROM mau_retention_by_cohort"
-//             .to_string(),
-//     );
-
-//     match sqlx::query_as::<_, MAURetentionByCohortResult>(&q)
-//         .fetch_all(pool)
-//         .await
-//     {
-//         Ok(results) => Ok(results),
-//         Err(e) => {
-//             log::error!("Error fetching MAU retention by cohort results: {}", e);
-//             Err(e)
-//         }
-//     }
-// }
+/// Cohorts and active users come from `dau`, the contributors' activity,
+/// while `inc_amt` sums `metric_dau` over each cohort's contributors.
+pub async fn mau_retention_by_cohort(
+    pool: &PgPool,
+    dau: String,
+    metric_dau: String,
+    params: &GrowthAccountingParams,
+) -> Result<Vec<MAURetentionByCohortResult>, sqlx::Error> {
+    let granularity = params.granularity;
+    // Every cohort gets a row for each period up to the last one with
+    // activity, so periods where nobody came back count as zero
+    let last_period =
+        granularity.periods_between("c.first_month", "(SELECT max(month) FROM mau_decorated)");
+    let active_month = format!(
+        "(c.first_month + p.months_since_first * interval '{}')",
+        granularity.interval()
+    );
+    let metric_month = format!("date_trunc('{}', m.dt)", granularity.unit());
+    let q = growth_accounting_query(
+        dau,
+        granularity,
+        format!(
+            "SELECT
+            c.first_month,
+            p.months_since_first::BIGINT,
+            COALESCE(r.active_users, 0)::BIGINT as active_users,
+            COALESCE(i.inc_amt, 0)::BIGINT as inc_amt
+        FROM (
+            SELECT DISTINCT first_month FROM mau_retention_by_cohort
+        ) c
+        CROSS JOIN LATERAL generate_series(0, ({})::INTEGER) AS p(months_since_first)
+        LEFT JOIN mau_retention_by_cohort r ON r.first_month = c.first_month
+            AND r.months_since_first = p.months_since_first
+        LEFT JOIN (
+            SELECT
+                f.first_month,
+                {} AS months_since_first,
+                sum(m.inc_amt) AS inc_amt
+            FROM ({}
+            ) m
+            JOIN first_dt f ON f.user_id = m.user_id
+            WHERE {} >= f.first_month
+            GROUP BY
+                1,
+                2
+        ) i ON i.first_month = c.first_month
+            AND i.months_since_first = p.months_since_first
+        WHERE {} AND {}
+        ORDER BY 1, 2",
+            last_period,
+            granularity.periods_between("f.first_month", &metric_month),
+            metric_dau,
+            metric_month,
+            params.range_condition("c.first_month"),
+            params.range_condition(&active_month)
+        ),
+    );
+
+    match sqlx::query_as::<_, MAURetentionByCohortResult>(&q)
+        .fetch_all(pool)
+        .await
+    {
+        Ok(results) => Ok(results),
+        Err(e) => {
+            log::error!("Error fetching MAU retention by cohort results: {}", e);
+            Err(e)
+        }
+    }
+}
+
+/// One row of the retention triangle. The lists are indexed by the number of
+/// periods since the cohort's first, and percentages are of the cohort's
+/// size.
+#[derive(Serialize, Debug, Deserialize)]
+pub struct RetentionCohort {
+    first_month: DateTime<Utc>,
+    cohort_size: i64,
+    active_users: Vec<i64>,
+    retained_pctg: Vec<f64>,
+    inc_amt: Vec<i64>,
+}
+
+pub fn retention_triangle(rows: Vec<MAURetentionByCohortResult>) -> Vec<RetentionCohort> {
+    let mut cohorts: Vec<RetentionCohort> = Vec::new();
+    for row in rows {
+        let cohort = match cohorts.last_mut() {
+            Some(cohort) if cohort.first_month == row.first_month => cohort,
+            _ => {
+                cohorts.push(RetentionCohort {
+                    first_month: row.first_month,
+                    cohort_size: row.active_users,
+                    active_users: Vec::new(),
+                    retained_pctg: Vec::new(),
+                    inc_amt: Vec::new(),
+                });
+                cohorts.last_mut().unwrap()
+            }
+        };
+        cohort.active_users.push(row.active_users);
+        cohort
+            .retained_pctg
+            .push(row.active_users as f64 / cohort.cohort_size.max(1) as f64);
+        cohort.inc_amt.push(row.inc_amt);
+    }
+    cohorts
+}
 
 #[derive(sqlx::FromRow, Serialize, Debug, Deserialize)]
 pub struct LTVCohortsCumulativeResult {
@@ -457,10 +1085,13 @@ pub struct LTVCohortsCumulativeResult {
 pub async fn ltv_cohorts_cumulative(
     pool: &PgPool,
     dau: String,
+    params: &GrowthAccountingParams,
 ) -> Result<Vec<LTVCohortsCumulativeResult>, sqlx::Error> {
     let q = growth_accounting_query(
         dau,
-        "SELECT
+        params.granularity,
+        format!(
+            "SELECT
             first_month,
             active_month,
             months_since_first::BIGINT,
@@ -470,8 +1101,12 @@ pub async fn ltv_cohorts_cumulative(
             inc_amt::BIGINT,
             cum_amt::BIGINT,
             cum_amt_per_user::DOUBLE PRECISION
-        FROM cohorts_cumulative_m"
-            .to_string(),
+        FROM cohorts_cumulative_m
+        WHERE {} AND {}
+        ORDER BY first_month, active_month",
+            params.range_condition("first_month"),
+            params.range_condition("active_month")
+        ),
     );
 
     match sqlx::query_as::<_, LTVCohortsCumulativeResult>(&q)
@@ -485,3 +1120,100 @@ pub async fn ltv_cohorts_cumulative(
         }
     }
 }
+
+#[derive(sqlx::FromRow, Serialize, Debug, Deserialize)]
+pub struct LTVWeeklyCohortsCumulativeResult {
+    first_week: DateTime<Utc>,
+    active_week: DateTime<Utc>,
+    weeks_since_first: i64,
+    users: i64,
+    cohort_num_users: i64,
+    retained_pctg: f64,
+    inc_amt: i64,
+    cum_amt: i64,
+    cum_amt_per_user: f64,
+}
+
+/// The cumulative cohorts at weekly resolution, whatever the requested
+/// granularity.
+pub async fn ltv_weekly_cohorts_cumulative(
+    pool: &PgPool,
+    dau: String,
+    params: &GrowthAccountingParams,
+) -> Result<Vec<LTVWeeklyCohortsCumulativeResult>, sqlx::Error> {
+    let q = growth_accounting_query(
+        dau,
+        params.granularity,
+        format!(
+            "SELECT
+            first_week,
+            active_week,
+            weeks_since_first::BIGINT,
+            users::BIGINT,
+            cohort_num_users::BIGINT,
+            retained_pctg::DOUBLE PRECISION,
+            inc_amt::BIGINT,
+            cum_amt::BIGINT,
+            cum_amt_per_user::DOUBLE PRECISION
+        FROM cohorts_cumulative
+        WHERE {} AND {}
+        ORDER BY first_week, active_week",
+            params.range_condition_in(Granularity::Week, "first_week"),
+            params.range_condition_in(Granularity::Week, "active_week")
+        ),
+    );
+
+    match sqlx::query_as::<_, LTVWeeklyCohortsCumulativeResult>(&q)
+        .fetch_all(pool)
+        .await
+    {
+        Ok(results) => Ok(results),
+        Err(e) => {
+            log::error!(
+                "Error fetching weekly LTV cohorts cumulative results: {}",
+                e
+            );
+            Err(e)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn window(value: &str) -> Result<String, String> {
+        TrailingWindow::try_from(value.to_string()).map(|window| window.interval)
+    }
+
+    #[test]
+    fn parses_trailing_windows() {
+        assert_eq!(window("30d").unwrap(), "30 days");
+        assert_eq!(window("4w").unwrap(), "4 weeks");
+        assert_eq!(window("12m").unwrap(), "12 months");
+        assert_eq!(window("2q").unwrap(), "6 months");
+        assert_eq!(window("1y").unwrap(), "1 years");
+        assert_eq!(window("1000y").unwrap(), "1000 years");
+    }
+
+    #[test]
+    fn rejects_malformed_windows() {
+        for value in ["", "m", "12", "12x", "0m", "-1m", "1.5m", "1é", "é", "12 m"] {
+            assert!(window(value).is_err(), "{:?} should be rejected", value);
+        }
+    }
+
+    #[test]
+    fn rejects_overflowing_windows() {
+        for value in [
+            "2000000000q",
+            "4294967295q",
+            "1001y",
+            "99999999m",
+            "4294967295d",
+        ] {
+            assert!(window(value).is_err(), "{:?} should be rejected", value);
+        }
+        assert!(window("99999999999m").is_err());
+    }
+}
@@ -0,0 +1,313 @@
+use crate::commit::SyncOutcome;
+use crate::contributor::{is_bot_account, link_github_account};
+use crate::error::AppError;
+use crate::github::with_backoff;
+use crate::job_queue::{is_cancel_requested, Job, SyncMode};
+use chrono::{DateTime, Utc};
+use log::info;
+use octocrab::models::issues::Issue;
+use octocrab::models::{Author, IssueState};
+use octocrab::params::{issues::Sort, Direction, State};
+use octocrab::Octocrab;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+
+/// An entry from the repository-wide issue event listing.
+#[derive(Deserialize)]
+struct IssueEvent {
+    id: u64,
+    actor: Option<Author>,
+    event: String,
+    created_at: DateTime<Utc>,
+    issue: Option<IssueEventIssue>,
+}
+
+#[derive(Deserialize)]
+struct IssueEventIssue {
+    number: u64,
+}
+
+#[derive(Serialize)]
+struct IssueEventParams {
+    per_page: u8,
+    page: u32,
+}
+
+/// Syncs the issues updated since the previous sync, then the comments on
+/// them and who closed them. A full sync fetches every issue again.
+pub async fn sync_issues(
+    job: &Job,
+    octocrab: &Octocrab,
+    pool: &PgPool,
+) -> Result<SyncOutcome, AppError> {
+    let started_at = Utc::now();
+    let since = match job.mode {
+        SyncMode::Full => None,
+        SyncMode::Incremental => {
+            sqlx::query_scalar!(
+                "SELECT issues_synced_at FROM repository WHERE repository_id = $1",
+                job.repository_id
+            )
+            .fetch_one(pool)
+            .await?
+        }
+    };
+
+    let mut page: u32 = 1;
+    let mut issues_synced: usize = 0;
+
+    loop {
+        let issues = with_backoff(|| async {
+            let issues = octocrab.issues(&job.owner, &job.name);
+            let mut request = issues
+                .list()
+                .state(State::All)
+                .sort(Sort::Updated)
+                .direction(Direction::Ascending)
+                .per_page(100)
+                .page(page);
+            if let Some(since) = since {
+                request = request.since(since);
+            }
+            request.send().await
+        })
+        .await?;
+        let has_next = issues.next.is_some();
+
+        for issue in issues.items.iter().filter(|i| i.pull_request.is_none()) {
+            upsert_issue(pool, job.repository_id, issue).await?;
+            issues_synced += 1;
+        }
+
+        if !has_next {
+            break;
+        }
+        if is_cancel_requested(pool, job.job_id).await? {
+            info!(
+                "Issue sync for repository {}/{} cancelled after {} issues",
+                job.owner, job.name, issues_synced
+            );
+            return Ok(SyncOutcome::Cancelled);
+        }
+        page += 1;
+    }
+
+    if let SyncOutcome::Cancelled = sync_issue_comments(job, octocrab, pool, since).await? {
+        return Ok(SyncOutcome::Cancelled);
+    }
+    if let SyncOutcome::Cancelled = sync_issue_closes(job, octocrab, pool, since).await? {
+        return Ok(SyncOutcome::Cancelled);
+    }
+
+    sqlx::query!(
+        "UPDATE repository SET issues_synced_at = $1 WHERE repository_id = $2",
+        started_at,
+        job.repository_id
+    )
+    .execute(pool)
+    .await?;
+
+    info!(
+        "Synced {} issues for repository {}/{}",
+        issues_synced, job.owner, job.name
+    );
+
+    Ok(SyncOutcome::Completed)
+}
+
+async fn upsert_issue(pool: &PgPool, repository_id: i32, issue: &Issue) -> Result<(), AppError> {
+    let state = match issue.state {
+        IssueState::Closed => "closed",
+        _ => "open",
+    };
+
+    sqlx::query!(
+        r#"
+        INSERT INTO issue (repository_id, number, title, state, author_login,
+        author_github_id, is_bot, created_at, updated_at, closed_at)
+        VALUES ($1, $2, $3, $4, $5, $6,
+            $7 OR EXISTS (
+                SELECT 1 FROM bot_pattern b
+                WHERE b.collection_id IS NULL AND $5 ~* b.pattern
+            ),
+            $8, $9, $10)
+        ON CONFLICT (repository_id, number) DO UPDATE
+        SET title = EXCLUDED.title,
+            state = EXCLUDED.state,
+            author_login = EXCLUDED.author_login,
+            author_github_id = EXCLUDED.author_github_id,
+            is_bot = EXCLUDED.is_bot,
+            updated_at = EXCLUDED.updated_at,
+            closed_at = EXCLUDED.closed_at
+        "#,
+        repository_id,
+        issue.number as i32,
+        issue.title,
+        state,
+        issue.user.login,
+        issue.user.id.0 as i64,
+        is_bot_account(&issue.user),
+        issue.created_at,
+        issue.updated_at,
+        issue.closed_at
+    )
+    .execute(pool)
+    .await?;
+
+    link_github_account(pool, &issue.user).await?;
+
+    Ok(())
+}
+
+/// Fetches the repository's issue comments in one listing. Comments on pull
+/// requests come back too and are skipped along with those on issues that
+/// have not been synced.
+async fn sync_issue_comments(
+    job: &Job,
+    octocrab: &Octocrab,
+    pool: &PgPool,
+    since: Option<DateTime<Utc>>,
+) -> Result<SyncOutcome, AppError> {
+    let mut page: u32 = 1;
+
+    loop {
+        let comments = with_backoff(|| async {
+            let issues = octocrab.issues(&job.owner, &job.name);
+            let mut request = issues
+                .list_issue_comments()
+                .sort(Sort::Updated)
+                .direction(Direction::Ascending)
+                .per_page(100)
+                .page(page);
+            if let Some(since) = since {
+                request = request.since(since);
+            }
+            request.send().await
+        })
+        .await?;
+        let has_next = comments.next.is_some();
+
+        for comment in comments.items {
+            let Some(number) = comment
+                .issue_url
+                .as_ref()
+                .and_then(|url| url.path_segments())
+                .and_then(|mut segments| segments.next_back())
+                .and_then(|number| number.parse::<i32>().ok())
+            else {
+                continue;
+            };
+
+            let result = sqlx::query!(
+                r#"
+                INSERT INTO issue_comment (comment_id, issue_id, author_login,
+                author_github_id, is_bot, created_at)
+                SELECT $1, i.issue_id, $4, $5,
+                    $6 OR EXISTS (
+                        SELECT 1 FROM bot_pattern b
+                        WHERE b.collection_id IS NULL AND $4 ~* b.pattern
+                    ),
+                    $7
+                FROM issue i
+                WHERE i.repository_id = $2 AND i.number = $3
+                ON CONFLICT (comment_id) DO UPDATE
+                SET author_login = EXCLUDED.author_login,
+                    author_github_id = EXCLUDED.author_github_id,
+                    is_bot = EXCLUDED.is_bot
+                "#,
+                comment.id.0 as i64,
+                job.repository_id,
+                number,
+                comment.user.login,
+                comment.user.id.0 as i64,
+                is_bot_account(&comment.user),
+                comment.created_at
+            )
+            .execute(pool)
+            .await?;
+
+            if result.rows_affected() > 0 {
+                link_github_account(pool, &comment.user).await?;
+            }
+        }
+
+        if !has_next {
+            return Ok(SyncOutcome::Completed);
+        }
+        if is_cancel_requested(pool, job.job_id).await? {
+            return Ok(SyncOutcome::Cancelled);
+        }
+        page += 1;
+    }
+}
+
+/// Records who closed each issue from the repository's issue events, which
+/// are listed newest first and cannot be filtered by date.
+async fn sync_issue_closes(
+    job: &Job,
+    octocrab: &Octocrab,
+    pool: &PgPool,
+    since: Option<DateTime<Utc>>,
+) -> Result<SyncOutcome, AppError> {
+    let route = format!("/repos/{}/{}/issues/events", job.owner, job.name);
+    let mut page: u32 = 1;
+
+    loop {
+        let params = IssueEventParams {
+            per_page: 100,
+            page,
+        };
+        let events: Vec<IssueEvent> =
+            with_backoff(|| async { octocrab.get(&route, Some(&params)).await }).await?;
+        let page_len = events.len();
+
+        let mut reached_synced = false;
+        for event in events {
+            if since.is_some_and(|since| event.created_at < since) {
+                reached_synced = true;
+                break;
+            }
+            let (Some(issue), "closed") = (event.issue, event.event.as_str()) else {
+                continue;
+            };
+            let actor = event.actor.as_ref();
+
+            let result = sqlx::query!(
+                r#"
+                INSERT INTO issue_close (event_id, issue_id, actor_login, actor_github_id,
+                is_bot, created_at)
+                SELECT $1, i.issue_id, $4, $5,
+                    $6 OR EXISTS (
+                        SELECT 1 FROM bot_pattern b
+                        WHERE b.collection_id IS NULL AND $4 ~* b.pattern
+                    ),
+                    $7
+                FROM issue i
+                WHERE i.repository_id = $2 AND i.number = $3
+                ON CONFLICT (event_id) DO NOTHING
+                "#,
+                event.id as i64,
+                job.repository_id,
+                issue.number as i32,
+                actor.map(|a| a.login.as_str()),
+                actor.map(|a| a.id.0 as i64),
+                actor.is_some_and(is_bot_account),
+                event.created_at
+            )
+            .execute(pool)
+            .await?;
+
+            if let (Some(actor), true) = (actor, result.rows_affected() > 0) {
+                link_github_account(pool, actor).await?;
+            }
+        }
+
+        if reached_synced || page_len < 100 {
+            return Ok(SyncOutcome::Completed);
+        }
+        if is_cancel_requested(pool, job.job_id).await? {
+            return Ok(SyncOutcome::Cancelled);
+        }
+        page += 1;
+    }
+}
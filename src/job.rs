@@ -0,0 +1,365 @@
+use crate::auth_utils::get_account_id;
+use crate::error::AppError;
+use crate::job_queue::CancelOutcome;
+use crate::types::PaginatedResponse;
+use crate::AppState;
+use actix_web::{web, HttpRequest, HttpResponse};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::PgPool;
+
+#[derive(Debug, Serialize)]
+pub struct SyncJob {
+    pub job_id: i32,
+    pub repository_id: i32,
+    pub owner: String,
+    pub name: String,
+    pub status: String,
+    /// `incremental` or `full`.
+    pub mode: String,
+    /// 1-based position among queued jobs; only set while the job is queued.
+    pub queue_position: Option<i64>,
+    pub pages_fetched: i32,
+    /// Commits fetched from GitHub by the latest attempt.
+    pub commits_seen: i32,
+    /// Commits that were not stored yet; for a full sync, the ones that
+    /// were missing.
+    pub commits_inserted: i32,
+    pub attempts: i32,
+    pub max_attempts: i32,
+    pub cancel_requested: bool,
+    /// Earliest time a queued job may run; later than `created_at` while
+    /// waiting to be retried.
+    pub run_after: DateTime<Utc>,
+    pub error: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub started_at: Option<DateTime<Utc>>,
+    pub finished_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RepositorySyncStatus {
+    repository_id: i32,
+    owner: String,
+    name: String,
+    indexed_at: Option<DateTime<Utc>>,
+    latest_commit_sha: Option<String>,
+    latest_commit_date: Option<DateTime<Utc>>,
+    job: Option<SyncJob>,
+}
+
+/// Jobs the caller may not act on are reported as missing rather than
+/// revealing their repository.
+pub async fn get_job(
+    state: web::Data<AppState>,
+    req: HttpRequest,
+    job_id: web::Path<i32>,
+) -> Result<HttpResponse, AppError> {
+    let job_id = job_id.into_inner();
+    match check_job_owner(&state.db_pool, &req, job_id).await {
+        Err(AppError::Unauthorized(_)) => {
+            return Err(AppError::NotFound("Job not found".into()));
+        }
+        result => result?,
+    }
+
+    match fetch_job(&state.db_pool, job_id).await? {
+        Some(job) => Ok(HttpResponse::Ok().json(job)),
+        None => Err(AppError::NotFound("Job not found".into())),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct DeadJobListQuery {
+    page: Option<i64>,
+    page_size: Option<i64>,
+}
+
+/// Lists the dead-lettered jobs the caller may retry: those run with their
+/// account or on repositories in their collections.
+pub async fn list_dead_jobs(
+    state: web::Data<AppState>,
+    req: HttpRequest,
+    query: web::Query<DeadJobListQuery>,
+) -> Result<HttpResponse, AppError> {
+    let account_id = get_account_id(&req)?;
+    let page = query.page.unwrap_or(1);
+    let page_size = query.page_size.unwrap_or(10);
+    if page < 1 || page_size < 1 {
+        return Err(AppError::BadRequest(
+            "page and page_size must be at least 1".into(),
+        ));
+    }
+    let offset = (page - 1) * page_size;
+
+    let total = sqlx::query_scalar!(
+        r#"
+        SELECT COUNT(*) FROM sync_job j
+        WHERE j.status = 'dead'
+        AND (
+            j.account_id = $1 OR EXISTS (
+                SELECT 1 FROM collection_repository cr
+                JOIN collection c ON c.collection_id = cr.collection_id
+                WHERE cr.repository_id = j.repository_id AND c.owner_id = $1
+            )
+        )
+        "#,
+        account_id
+    )
+    .fetch_one(&state.db_pool)
+    .await?
+    .unwrap_or(0);
+
+    let jobs = sqlx::query_as!(
+        SyncJob,
+        r#"
+        SELECT
+            j.sync_job_id as job_id,
+            j.repository_id,
+            r.owner,
+            r.name,
+            j.status,
+            j.mode,
+            NULL::bigint as "queue_position?",
+            j.pages_fetched,
+            j.commits_seen,
+            j.commits_inserted,
+            j.attempts,
+            j.max_attempts,
+            j.cancel_requested,
+            j.run_after,
+            j.error,
+            j.created_at,
+            j.started_at,
+            j.finished_at
+        FROM sync_job j
+        JOIN repository r ON r.repository_id = j.repository_id
+        WHERE j.status = 'dead'
+        AND (
+            j.account_id = $3 OR EXISTS (
+                SELECT 1 FROM collection_repository cr
+                JOIN collection c ON c.collection_id = cr.collection_id
+                WHERE cr.repository_id = j.repository_id AND c.owner_id = $3
+            )
+        )
+        ORDER BY j.finished_at DESC, j.sync_job_id DESC
+        LIMIT $1 OFFSET $2
+        "#,
+        page_size,
+        offset,
+        account_id
+    )
+    .fetch_all(&state.db_pool)
+    .await?;
+
+    let total_pages = (total as f64 / page_size as f64).ceil() as i64;
+    Ok(HttpResponse::Ok().json(PaginatedResponse {
+        data: jobs,
+        total,
+        page,
+        page_size,
+        total_pages,
+    }))
+}
+
+pub async fn redrive_job(
+    state: web::Data<AppState>,
+    req: HttpRequest,
+    job_id: web::Path<i32>,
+) -> Result<HttpResponse, AppError> {
+    let job_id = job_id.into_inner();
+    check_job_owner(&state.db_pool, &req, job_id).await?;
+
+    match state.job_queue.redrive(job_id).await? {
+        Some(queued_job_id) => Ok(HttpResponse::Accepted().json(json!({
+            "message": "Job requeued",
+            "job_id": queued_job_id
+        }))),
+        None => match fetch_job(&state.db_pool, job_id).await? {
+            Some(_) => Err(AppError::BadRequest(
+                "Only dead-lettered jobs can be retried".into(),
+            )),
+            None => Err(AppError::NotFound("Job not found".into())),
+        },
+    }
+}
+
+pub async fn cancel_job(
+    state: web::Data<AppState>,
+    req: HttpRequest,
+    job_id: web::Path<i32>,
+) -> Result<HttpResponse, AppError> {
+    let job_id = job_id.into_inner();
+    check_job_owner(&state.db_pool, &req, job_id).await?;
+
+    match state.job_queue.cancel(job_id).await? {
+        Some(CancelOutcome::Cancelled) => Ok(HttpResponse::Ok().json(json!({
+            "message": "Job cancelled",
+            "job_id": job_id
+        }))),
+        Some(CancelOutcome::CancelRequested) => Ok(HttpResponse::Accepted().json(json!({
+            "message": "Job will stop after the current page of commits",
+            "job_id": job_id
+        }))),
+        None => match fetch_job(&state.db_pool, job_id).await? {
+            Some(_) => Err(AppError::BadRequest(
+                "Only queued or running jobs can be cancelled".into(),
+            )),
+            None => Err(AppError::NotFound("Job not found".into())),
+        },
+    }
+}
+
+pub async fn get_repository_sync_status(
+    state: web::Data<AppState>,
+    path: web::Path<(String, String)>,
+) -> Result<HttpResponse, AppError> {
+    let (owner, name) = path.into_inner();
+
+    let repository = sqlx::query!(
+        r#"
+        SELECT
+            r.repository_id,
+            r.owner,
+            r.name,
+            r.indexed_at,
+            c.sha as "latest_commit_sha?",
+            c.date as "latest_commit_date?"
+        FROM repository r
+        LEFT JOIN LATERAL (
+            SELECT sha, date FROM commit
+            WHERE repository_id = r.repository_id
+            ORDER BY date DESC
+            LIMIT 1
+        ) c ON true
+        WHERE r.owner = $1 AND r.name = $2
+        "#,
+        owner,
+        name
+    )
+    .fetch_optional(&state.db_pool)
+    .await?
+    .ok_or_else(|| AppError::NotFound("Repository not found in database".into()))?;
+
+    let job = fetch_latest_repository_job(&state.db_pool, repository.repository_id).await?;
+
+    Ok(HttpResponse::Ok().json(RepositorySyncStatus {
+        repository_id: repository.repository_id,
+        owner: repository.owner,
+        name: repository.name,
+        indexed_at: repository.indexed_at,
+        latest_commit_sha: repository.latest_commit_sha,
+        latest_commit_date: repository.latest_commit_date,
+        job,
+    }))
+}
+
+/// A job may be acted on by the account it runs as, or by the owner of a
+/// collection containing its repository.
+async fn check_job_owner(pool: &PgPool, req: &HttpRequest, job_id: i32) -> Result<(), AppError> {
+    let account_id = get_account_id(req)?;
+    let job = sqlx::query!(
+        r#"
+        SELECT
+            j.account_id IS NOT DISTINCT FROM $2 OR EXISTS (
+                SELECT 1 FROM collection_repository cr
+                JOIN collection c ON c.collection_id = cr.collection_id
+                WHERE cr.repository_id = j.repository_id AND c.owner_id = $2
+            ) AS "owned!"
+        FROM sync_job j
+        WHERE j.sync_job_id = $1
+        "#,
+        job_id,
+        account_id
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    match job {
+        Some(job) if job.owned => Ok(()),
+        Some(_) => Err(AppError::Unauthorized(
+            "You do not own this job or its repository".into(),
+        )),
+        None => Err(AppError::NotFound("Job not found".into())),
+    }
+}
+
+async fn fetch_job(pool: &PgPool, job_id: i32) -> Result<Option<SyncJob>, sqlx::Error> {
+    sqlx::query_as!(
+        SyncJob,
+        r#"
+        SELECT
+            j.sync_job_id as job_id,
+            j.repository_id,
+            r.owner,
+            r.name,
+            j.status,
+            j.mode,
+            CASE WHEN j.status = 'queued' THEN (
+                SELECT COUNT(*) FROM sync_job q
+                WHERE q.status = 'queued'
+                AND (q.run_after, q.sync_job_id) <= (j.run_after, j.sync_job_id)
+            ) END as "queue_position?",
+            j.pages_fetched,
+            j.commits_seen,
+            j.commits_inserted,
+            j.attempts,
+            j.max_attempts,
+            j.cancel_requested,
+            j.run_after,
+            j.error,
+            j.created_at,
+            j.started_at,
+            j.finished_at
+        FROM sync_job j
+        JOIN repository r ON r.repository_id = j.repository_id
+        WHERE j.sync_job_id = $1
+        "#,
+        job_id
+    )
+    .fetch_optional(pool)
+    .await
+}
+
+async fn fetch_latest_repository_job(
+    pool: &PgPool,
+    repository_id: i32,
+) -> Result<Option<SyncJob>, sqlx::Error> {
+    sqlx::query_as!(
+        SyncJob,
+        r#"
+        SELECT
+            j.sync_job_id as job_id,
+            j.repository_id,
+            r.owner,
+            r.name,
+            j.status,
+            j.mode,
+            CASE WHEN j.status = 'queued' THEN (
+                SELECT COUNT(*) FROM sync_job q
+                WHERE q.status = 'queued'
+                AND (q.run_after, q.sync_job_id) <= (j.run_after, j.sync_job_id)
+            ) END as "queue_position?",
+            j.pages_fetched,
+            j.commits_seen,
+            j.commits_inserted,
+            j.attempts,
+            j.max_attempts,
+            j.cancel_requested,
+            j.run_after,
+            j.error,
+            j.created_at,
+            j.started_at,
+            j.finished_at
+        FROM sync_job j
+        JOIN repository r ON r.repository_id = j.repository_id
+        WHERE j.repository_id = $1
+        ORDER BY j.created_at DESC, j.sync_job_id DESC
+        LIMIT 1
+        "#,
+        repository_id
+    )
+    .fetch_optional(pool)
+    .await
+}
@@ -1,41 +1,143 @@
-use crate::commit::fetch_and_persist_commits;
+use crate::commit::{backfill_commit_stats, fetch_and_persist_commits, SyncOutcome};
 use crate::error::AppError;
-use crate::job_queue::{Job, JobQueue};
+use crate::issue::sync_issues;
+use crate::job_queue::{FailureOutcome, Job, JobQueue};
+use crate::pull_request::{backfill_reviews, sync_pull_requests};
+use crate::settings::JobConfig;
+use crate::token_store::TokenStore;
+use http::StatusCode;
 use log::{error, info};
 use octocrab::Octocrab;
 use sqlx::postgres::PgPool;
 use std::sync::Arc;
 
-pub async fn process_jobs(queue: Arc<JobQueue>, pool: PgPool) {
+/// Runs the configured number of workers, each claiming jobs until the
+/// queue is drained and then waiting to be woken up.
+pub async fn process_jobs(queue: Arc<JobQueue>, pool: PgPool, token_store: Arc<TokenStore>) {
+    let workers = queue.config().workers.max(1);
+    info!("Starting {} sync workers", workers);
+
+    let handles: Vec<_> = (0..workers)
+        .map(|_| tokio::spawn(run_worker(queue.clone(), pool.clone(), token_store.clone())))
+        .collect();
+    futures::future::join_all(handles).await;
+}
+
+async fn run_worker(queue: Arc<JobQueue>, pool: PgPool, token_store: Arc<TokenStore>) {
     loop {
-        if let Some(job) = queue.pop().await {
-            info!("Processing job for repository: {}/{}", job.owner, job.name);
-            let job_clone = job.clone();
-            match process_single_job(job, pool.clone()).await {
-                Ok(_) => info!(
-                    "Job completed successfully for repository: {}/{}",
-                    job_clone.owner, job_clone.name
-                ),
-                Err(e) => {
-                    error!(
-                        "Job failed for repository: {}/{}: {:?}",
-                        job_clone.owner, job_clone.name, e
-                    );
-                    // Re-queue the job
-                    // This is a bad idea, as it can put the queue into an infinite loop
-                    // queue.push(job_clone).await;
+        match queue.claim().await {
+            Ok(Some(job)) => {
+                info!("Processing job for repository: {}/{}", job.owner, job.name);
+                let job_clone = job.clone();
+                match process_single_job(job, pool.clone(), &token_store, queue.config()).await {
+                    Ok(SyncOutcome::Completed) => {
+                        info!(
+                            "Job completed successfully for repository: {}/{}",
+                            job_clone.owner, job_clone.name
+                        );
+                        if let Err(e) = queue.complete(job_clone.job_id).await {
+                            error!(
+                                "Failed to mark job {} as succeeded: {:?}",
+                                job_clone.job_id, e
+                            );
+                        }
+                    }
+                    Ok(SyncOutcome::Cancelled) => {
+                        info!(
+                            "Job cancelled for repository: {}/{}",
+                            job_clone.owner, job_clone.name
+                        );
+                        if let Err(e) = queue.mark_cancelled(job_clone.job_id).await {
+                            error!(
+                                "Failed to mark job {} as cancelled: {:?}",
+                                job_clone.job_id, e
+                            );
+                        }
+                    }
+                    Err(e) => {
+                        error!(
+                            "Job failed for repository: {}/{}: {:?}",
+                            job_clone.owner, job_clone.name, e
+                        );
+                        // Without a usable token retrying cannot help
+                        let retryable = !matches!(e, AppError::Unauthorized(_));
+                        match queue
+                            .fail(job_clone.job_id, &e.to_string(), retryable)
+                            .await
+                        {
+                            Ok(FailureOutcome::Retrying) => {
+                                info!("Job {} will be retried", job_clone.job_id)
+                            }
+                            Ok(FailureOutcome::DeadLettered) => error!(
+                                "Job {} exhausted its attempts and was dead-lettered",
+                                job_clone.job_id
+                            ),
+                            Ok(FailureOutcome::Cancelled) => {
+                                info!("Job {} was cancelled", job_clone.job_id)
+                            }
+                            Err(e) => {
+                                error!("Failed to mark job {} as failed: {:?}", job_clone.job_id, e)
+                            }
+                        }
+                    }
                 }
+                // Look for more work straight away while the queue is busy
+                continue;
             }
+            Ok(None) => {}
+            Err(e) => error!("Failed to claim job from queue: {:?}", e),
         }
 
-        tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+        queue.wait_for_work().await;
     }
 }
 
-async fn process_single_job(job: Job, pool: PgPool) -> Result<(), AppError> {
+async fn process_single_job(
+    job: Job,
+    pool: PgPool,
+    token_store: &TokenStore,
+    config: &JobConfig,
+) -> Result<SyncOutcome, AppError> {
+    let github_token = token_store.resolve(&pool, job.account_id).await?;
     let github_client = Octocrab::builder()
-        .personal_token(job.github_token.clone())
+        .personal_token(github_token)
         .build()
         .map_err(AppError::GitHub)?;
-    fetch_and_persist_commits(&job, &github_client, &pool).await
+
+    let result = async {
+        if let SyncOutcome::Cancelled =
+            fetch_and_persist_commits(&job, &github_client, &pool).await?
+        {
+            return Ok(SyncOutcome::Cancelled);
+        }
+        if let SyncOutcome::Cancelled = sync_pull_requests(&job, &github_client, &pool).await? {
+            return Ok(SyncOutcome::Cancelled);
+        }
+        if let SyncOutcome::Cancelled = sync_issues(&job, &github_client, &pool).await? {
+            return Ok(SyncOutcome::Cancelled);
+        }
+        // Reviews and stats cost a request per pull request or commit, so they
+        // go last and stop short of the rate limit
+        if let SyncOutcome::Cancelled =
+            backfill_reviews(&job, &github_client, &pool, config.stats_rate_limit_reserve).await?
+        {
+            return Ok(SyncOutcome::Cancelled);
+        }
+        backfill_commit_stats(&job, &github_client, &pool, config.stats_rate_limit_reserve).await
+    }
+    .await;
+
+    match result {
+        Err(AppError::GitHub(octocrab::Error::GitHub { source, .. }))
+            if source.status_code == StatusCode::UNAUTHORIZED =>
+        {
+            if let Some(account_id) = job.account_id {
+                token_store.revoke(&pool, account_id).await?;
+            }
+            Err(AppError::Unauthorized(
+                "GitHub token has been revoked".into(),
+            ))
+        }
+        result => result,
+    }
 }
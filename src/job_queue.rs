@@ -1,30 +1,422 @@
+use crate::settings::JobConfig;
+use serde::{Deserialize, Serialize};
+use sqlx::postgres::PgPool;
 use std::sync::Arc;
-use tokio::sync::Mutex;
+use std::time::Duration;
+use tokio::sync::{Mutex, Notify};
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SyncMode {
+    /// Fetch the commits added since the branch head seen on the last sync.
+    #[default]
+    Incremental,
+    /// Walk the entire history and add any commits that are missing.
+    Full,
+}
+
+impl SyncMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SyncMode::Incremental => "incremental",
+            SyncMode::Full => "full",
+        }
+    }
+
+    fn from_db(mode: &str) -> Self {
+        match mode {
+            "full" => SyncMode::Full,
+            _ => SyncMode::Incremental,
+        }
+    }
+}
 
 #[derive(Clone)]
 pub struct Job {
+    pub job_id: i32,
     pub repository_id: i32,
     pub owner: String,
     pub name: String,
-    pub github_token: String,
+    /// Account whose GitHub token the job runs with; None means the
+    /// server's own token.
+    pub account_id: Option<i32>,
+    pub mode: SyncMode,
+}
+
+pub struct EnqueuedJob {
+    pub job_id: i32,
+    /// False when the request was coalesced into an existing job.
+    pub is_new: bool,
+    /// False when a full sync was requested but the existing job is already
+    /// running as an incremental one.
+    pub mode_applied: bool,
 }
 
+/// What happened to a job after a failed attempt.
+#[derive(Debug, PartialEq)]
+pub enum FailureOutcome {
+    Retrying,
+    DeadLettered,
+    /// The job was asked to cancel while the failing attempt ran.
+    Cancelled,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum CancelOutcome {
+    /// The job was still queued and will never run.
+    Cancelled,
+    /// The job is running and will stop after the page it is fetching.
+    CancelRequested,
+}
+
+/// Repository sync queue backed by the `sync_job` table, so pending syncs
+/// survive a restart.
 pub struct JobQueue {
-    pub queue: Mutex<Vec<Job>>,
+    pool: PgPool,
+    config: JobConfig,
+    /// Wakes idle workers when a job is queued or a running one finishes.
+    notify: Notify,
+    /// Serializes claims within this process so the per-token limit cannot
+    /// be overshot by two workers counting running jobs at the same time.
+    claim_lock: Mutex<()>,
 }
 
 impl JobQueue {
-    pub fn new() -> Arc<Self> {
+    pub fn new(pool: PgPool, config: JobConfig) -> Arc<Self> {
         Arc::new(Self {
-            queue: Mutex::new(Vec::new()),
+            pool,
+            config,
+            notify: Notify::new(),
+            claim_lock: Mutex::new(()),
         })
     }
 
-    pub async fn push(&self, job: Job) {
-        self.queue.lock().await.push(job);
+    pub fn config(&self) -> &JobConfig {
+        &self.config
+    }
+
+    /// Waits until a worker may have something to claim, or until the poll
+    /// interval elapses so retries that have become due are picked up.
+    pub async fn wait_for_work(&self) {
+        let poll_interval = Duration::from_secs(self.config.poll_interval_secs);
+        let _ = tokio::time::timeout(poll_interval, self.notify.notified()).await;
     }
 
-    pub async fn pop(&self) -> Option<Job> {
-        self.queue.lock().await.pop()
+    /// Queues a sync for the repository. If one is already queued or running
+    /// the request is coalesced into it and the existing job is returned. A
+    /// queued job is upgraded to a full sync when one is requested; a running
+    /// job keeps the mode it started with, which `mode_applied` reports.
+    pub async fn push(
+        &self,
+        repository_id: i32,
+        account_id: Option<i32>,
+        mode: SyncMode,
+    ) -> Result<EnqueuedJob, sqlx::Error> {
+        loop {
+            let inserted = sqlx::query!(
+                r#"
+                INSERT INTO sync_job (repository_id, account_id, max_attempts, mode)
+                VALUES ($1, $2, $3, $4)
+                ON CONFLICT (repository_id)
+                WHERE status IN ('queued', 'running') AND NOT cancel_requested
+                DO NOTHING
+                RETURNING sync_job_id
+                "#,
+                repository_id,
+                account_id,
+                self.config.max_attempts,
+                mode.as_str()
+            )
+            .fetch_optional(&self.pool)
+            .await?;
+
+            if let Some(row) = inserted {
+                self.notify.notify_one();
+                return Ok(EnqueuedJob {
+                    job_id: row.sync_job_id,
+                    is_new: true,
+                    mode_applied: true,
+                });
+            }
+
+            if let Some(job_id) = self.active_job_id(repository_id).await? {
+                let mode_applied = match mode {
+                    SyncMode::Incremental => true,
+                    SyncMode::Full => {
+                        let result = sqlx::query!(
+                            r#"
+                            UPDATE sync_job SET mode = 'full'
+                            WHERE sync_job_id = $1 AND (status = 'queued' OR mode = 'full')
+                            "#,
+                            job_id
+                        )
+                        .execute(&self.pool)
+                        .await?;
+                        result.rows_affected() > 0
+                    }
+                };
+                if mode_applied || self.active_job_id(repository_id).await? == Some(job_id) {
+                    return Ok(EnqueuedJob {
+                        job_id,
+                        is_new: false,
+                        mode_applied,
+                    });
+                }
+            }
+            // The conflicting job finished in the meantime, so try again
+        }
+    }
+
+    async fn active_job_id(&self, repository_id: i32) -> Result<Option<i32>, sqlx::Error> {
+        let row = sqlx::query!(
+            r#"
+            SELECT sync_job_id FROM sync_job
+            WHERE repository_id = $1 AND status IN ('queued', 'running')
+            AND NOT cancel_requested
+            "#,
+            repository_id
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.map(|row| row.sync_job_id))
+    }
+
+    /// Claims the oldest queued job that is due and whose account's GitHub
+    /// token is below its concurrency limit, and marks it as running. `SKIP LOCKED`
+    /// keeps concurrent claimers from picking the same job.
+    pub async fn claim(&self) -> Result<Option<Job>, sqlx::Error> {
+        let _guard = self.claim_lock.lock().await;
+        let row = sqlx::query!(
+            r#"
+            WITH next_job AS (
+                SELECT q.sync_job_id
+                FROM sync_job q
+                WHERE q.status = 'queued' AND q.run_after <= NOW()
+                AND (
+                    SELECT COUNT(*) FROM sync_job r
+                    WHERE r.status = 'running'
+                    AND r.account_id IS NOT DISTINCT FROM q.account_id
+                ) < $1
+                ORDER BY q.run_after, q.sync_job_id
+                LIMIT 1
+                FOR UPDATE SKIP LOCKED
+            ),
+            claimed AS (
+                UPDATE sync_job j
+                SET status = 'running',
+                    attempts = j.attempts + 1,
+                    pages_fetched = 0,
+                    commits_seen = 0,
+                    started_at = NOW()
+                FROM next_job
+                WHERE j.sync_job_id = next_job.sync_job_id
+                RETURNING j.sync_job_id, j.repository_id, j.account_id, j.mode
+            )
+            SELECT c.sync_job_id, c.repository_id, c.account_id, c.mode, r.owner, r.name
+            FROM claimed c
+            JOIN repository r ON r.repository_id = c.repository_id
+            "#,
+            self.config.max_jobs_per_token
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.map(|row| Job {
+            job_id: row.sync_job_id,
+            repository_id: row.repository_id,
+            owner: row.owner,
+            name: row.name,
+            account_id: row.account_id,
+            mode: SyncMode::from_db(&row.mode),
+        }))
     }
+
+    pub async fn complete(&self, job_id: i32) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"
+            UPDATE sync_job
+            SET status = 'succeeded', finished_at = NOW()
+            WHERE sync_job_id = $1
+            "#,
+            job_id
+        )
+        .execute(&self.pool)
+        .await?;
+        // The token's slot is free again, so a held-back job may be claimable
+        self.notify.notify_one();
+
+        Ok(())
+    }
+
+    /// Records a failed attempt. The job is queued again after an
+    /// exponentially growing delay until it runs out of attempts, at which
+    /// point it is dead-lettered with the error kept for inspection. A job
+    /// that was asked to cancel is cancelled instead of retried, and one that
+    /// failed with a non-retryable error is dead-lettered straight away.
+    pub async fn fail(
+        &self,
+        job_id: i32,
+        error: &str,
+        retryable: bool,
+    ) -> Result<FailureOutcome, sqlx::Error> {
+        let row = sqlx::query!(
+            r#"
+            UPDATE sync_job
+            SET status = CASE
+                    WHEN cancel_requested THEN 'cancelled'
+                    WHEN attempts >= max_attempts OR NOT $4 THEN 'dead'
+                    ELSE 'queued'
+                    END,
+                run_after = CASE WHEN cancel_requested OR attempts >= max_attempts OR NOT $4
+                    THEN run_after
+                    ELSE NOW() + make_interval(secs => $3 * power(2, attempts - 1))
+                    END,
+                started_at = CASE WHEN cancel_requested OR attempts >= max_attempts OR NOT $4
+                    THEN started_at
+                    END,
+                finished_at = CASE WHEN cancel_requested OR attempts >= max_attempts OR NOT $4
+                    THEN NOW()
+                    END,
+                error = $2
+            WHERE sync_job_id = $1
+            RETURNING status
+            "#,
+            job_id,
+            error,
+            self.config.retry_base_delay_secs as f64,
+            retryable
+        )
+        .fetch_one(&self.pool)
+        .await?;
+        self.notify.notify_one();
+
+        Ok(match row.status.as_str() {
+            "dead" => FailureOutcome::DeadLettered,
+            "cancelled" => FailureOutcome::Cancelled,
+            _ => FailureOutcome::Retrying,
+        })
+    }
+
+    /// Cancels a queued job outright, or asks a running one to stop between
+    /// commit pages. Returns None if the job is neither queued nor running.
+    pub async fn cancel(&self, job_id: i32) -> Result<Option<CancelOutcome>, sqlx::Error> {
+        let row = sqlx::query!(
+            r#"
+            UPDATE sync_job
+            SET status = CASE WHEN status = 'queued' THEN 'cancelled' ELSE status END,
+                finished_at = CASE WHEN status = 'queued' THEN NOW() ELSE finished_at END,
+                cancel_requested = true
+            WHERE sync_job_id = $1 AND status IN ('queued', 'running')
+            RETURNING status
+            "#,
+            job_id
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.map(|row| {
+            if row.status == "cancelled" {
+                CancelOutcome::Cancelled
+            } else {
+                CancelOutcome::CancelRequested
+            }
+        }))
+    }
+
+    /// Marks a running job that stopped early because it was cancelled.
+    pub async fn mark_cancelled(&self, job_id: i32) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"
+            UPDATE sync_job
+            SET status = 'cancelled', finished_at = NOW()
+            WHERE sync_job_id = $1
+            "#,
+            job_id
+        )
+        .execute(&self.pool)
+        .await?;
+        self.notify.notify_one();
+
+        Ok(())
+    }
+
+    /// Moves a dead-lettered job back into the queue with a fresh set of
+    /// attempts and returns the id of the job that will sync the repository.
+    /// That is a different job if the repository already has one queued or
+    /// running. Returns None if the job is not dead-lettered.
+    pub async fn redrive(&self, job_id: i32) -> Result<Option<i32>, sqlx::Error> {
+        let result = sqlx::query!(
+            r#"
+            UPDATE sync_job
+            SET status = 'queued',
+                attempts = 0,
+                max_attempts = $2,
+                run_after = NOW(),
+                started_at = NULL,
+                finished_at = NULL
+            WHERE sync_job_id = $1 AND status = 'dead'
+            RETURNING repository_id
+            "#,
+            job_id,
+            self.config.max_attempts
+        )
+        .fetch_optional(&self.pool)
+        .await;
+
+        match result {
+            Ok(Some(_)) => {
+                self.notify.notify_one();
+                Ok(Some(job_id))
+            }
+            Ok(None) => Ok(None),
+            Err(sqlx::Error::Database(e)) if e.is_unique_violation() => {
+                let repository_id = sqlx::query_scalar!(
+                    "SELECT repository_id FROM sync_job WHERE sync_job_id = $1",
+                    job_id
+                )
+                .fetch_one(&self.pool)
+                .await?;
+                self.active_job_id(repository_id).await
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Puts jobs that were running when the server last stopped back in the
+    /// queue, except those that were being cancelled. Only call this before
+    /// any worker has started.
+    pub async fn requeue_running(&self) -> Result<u64, sqlx::Error> {
+        sqlx::query!(
+            r#"
+            UPDATE sync_job
+            SET status = 'cancelled', finished_at = NOW()
+            WHERE status = 'running' AND cancel_requested
+            "#
+        )
+        .execute(&self.pool)
+        .await?;
+
+        let result = sqlx::query!(
+            r#"
+            UPDATE sync_job
+            SET status = 'queued', started_at = NULL
+            WHERE status = 'running'
+            "#
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+}
+
+/// Lets a running job check between pages whether it has been asked to stop.
+pub async fn is_cancel_requested(pool: &PgPool, job_id: i32) -> Result<bool, sqlx::Error> {
+    sqlx::query_scalar!(
+        "SELECT cancel_requested FROM sync_job WHERE sync_job_id = $1",
+        job_id
+    )
+    .fetch_one(pool)
+    .await
 }
@@ -1,7 +1,18 @@
 use crate::account::{get_profile_data, get_repo_collections};
 use crate::auth::logout;
+use crate::contributor::{
+    get_contributor, list_contributors, merge_contributors, split_contributor,
+};
+use crate::error::AppError;
+use crate::job::{cancel_job, get_job, get_repository_sync_status, list_dead_jobs, redrive_job};
 use crate::job_queue::JobQueue;
 use crate::middleware::AuthMiddleware;
+use crate::scheduler::{
+    delete_collection_schedule, delete_repository_schedule, get_collection_schedule,
+    get_repository_schedule, update_collection_schedule, update_repository_schedule,
+};
+use crate::settings::Settings;
+use crate::token_store::TokenStore;
 use actix_cors::Cors;
 use actix_web::http::header;
 use actix_web::{middleware::Logger, web, App, HttpResponse, HttpServer};
@@ -15,44 +26,85 @@ mod auth;
 mod auth_utils;
 mod collection;
 mod commit;
+mod contributor;
 mod db;
 mod error;
 mod github;
 mod growth_accounting;
+mod issue;
+mod job;
 mod job_processor;
 mod job_queue;
 mod middleware;
+mod pull_request;
 mod repository;
+mod scheduler;
+mod settings;
+mod token_store;
 mod types;
 
 use collection::{
-    add_repository_to_collection, create_collection, delete_collection, get_collection,
-    get_collection_growth_accounting, get_collections, remove_repository_from_collection,
+    add_collection_bot_pattern, add_repository_to_collection, create_collection, delete_collection,
+    get_collection, get_collection_bot_patterns, get_collection_growth_accounting,
+    get_collection_retention, get_collection_stickiness, get_collection_weekly_ltv,
+    get_collections, remove_collection_bot_pattern, remove_repository_from_collection,
     update_collection,
 };
 use repository::{
-    create_repository, get_repository_ga, get_repository_metadata, list_repositories,
-    sync_repository,
+    create_repository, get_repository_branches, get_repository_ga, get_repository_metadata,
+    get_repository_retention, get_repository_stickiness, get_repository_weekly_ltv,
+    list_repositories, sync_repository, update_repository_branches,
 };
 
 pub struct AppState {
     pub db_pool: PgPool,
     pub job_queue: Arc<JobQueue>,
+    pub token_store: Arc<TokenStore>,
 }
 
 #[actix_web::main]
 async fn main() -> io::Result<()> {
     env_logger::init_from_env(env_logger::Env::new().default_filter_or("debug"));
 
-    let pool: PgPool = db::create_pool().await.expect("Failed to create pool");
-    let job_queue = JobQueue::new();
+    let settings = Settings::new()
+        .map_err(|e| AppError::Configuration(e.to_string()))
+        .expect("Failed to load configuration");
+    let pool: PgPool = db::create_pool(&settings.database)
+        .await
+        .expect("Failed to create pool");
+    let token_store = Arc::new(TokenStore::from_env().expect("Failed to create token store"));
+    let job_queue = JobQueue::new(pool.clone(), settings.jobs.clone());
+    let requeued = job_queue
+        .requeue_running()
+        .await
+        .expect("Failed to requeue interrupted jobs");
+    if requeued > 0 {
+        info!("Requeued {} interrupted sync jobs", requeued);
+    }
+
+    tokio::spawn(job_processor::process_jobs(
+        job_queue.clone(),
+        pool.clone(),
+        token_store.clone(),
+    ));
 
-    tokio::spawn(job_processor::process_jobs(job_queue.clone(), pool.clone()));
+    if settings.scheduler.enabled {
+        if token_store.has_service_token() {
+            tokio::spawn(scheduler::run_scheduler(
+                job_queue.clone(),
+                pool.clone(),
+                settings.scheduler.clone(),
+            ));
+        } else {
+            log::warn!("GITHUB_TOKEN is not set; scheduled re-syncs are disabled");
+        }
+    }
 
     // Create the application state
     let app_state = web::Data::new(AppState {
         db_pool: pool.clone(),
         job_queue,
+        token_store,
     });
 
     info!("Starting server at http://localhost:8080");
@@ -103,7 +155,54 @@ async fn main() -> io::Result<()> {
                                     .route(web::put().to(sync_repository))
                                     .route(web::get().to(get_repository_metadata)),
                             )
-                            .route("/{owner}/{name}/ga", web::get().to(get_repository_ga)),
+                            .route("/{owner}/{name}/ga", web::get().to(get_repository_ga))
+                            .route(
+                                "/{owner}/{name}/stickiness",
+                                web::get().to(get_repository_stickiness),
+                            )
+                            .route(
+                                "/{owner}/{name}/retention",
+                                web::get().to(get_repository_retention),
+                            )
+                            .route(
+                                "/{owner}/{name}/ltv/weekly",
+                                web::get().to(get_repository_weekly_ltv),
+                            )
+                            .route(
+                                "/{owner}/{name}/sync",
+                                web::get().to(get_repository_sync_status),
+                            )
+                            .service(
+                                web::resource("/{owner}/{name}/branches")
+                                    .route(web::get().to(get_repository_branches))
+                                    .route(web::put().to(update_repository_branches)),
+                            )
+                            .service(
+                                web::resource("/{owner}/{name}/schedule")
+                                    .route(web::get().to(get_repository_schedule))
+                                    .route(web::put().to(update_repository_schedule))
+                                    .route(web::delete().to(delete_repository_schedule)),
+                            ),
+                    )
+                    .service(
+                        web::scope("/contributors")
+                            .route("", web::get().to(list_contributors))
+                            .route("/{contributor_id}", web::get().to(get_contributor))
+                            .route(
+                                "/{contributor_id}/merge",
+                                web::post().to(merge_contributors),
+                            )
+                            .route("/{contributor_id}/split", web::post().to(split_contributor)),
+                    )
+                    .service(
+                        web::scope("/jobs")
+                            .route("/dead", web::get().to(list_dead_jobs))
+                            .service(
+                                web::resource("/{job_id}")
+                                    .route(web::get().to(get_job))
+                                    .route(web::delete().to(cancel_job)),
+                            )
+                            .route("/{job_id}/retry", web::post().to(redrive_job)),
                     )
                     .service(
                         web::scope("/collections")
@@ -133,6 +232,33 @@ async fn main() -> io::Result<()> {
                             .route(
                                 "/{collection_id}/ga",
                                 web::get().to(get_collection_growth_accounting),
+                            )
+                            .route(
+                                "/{collection_id}/stickiness",
+                                web::get().to(get_collection_stickiness),
+                            )
+                            .route(
+                                "/{collection_id}/retention",
+                                web::get().to(get_collection_retention),
+                            )
+                            .route(
+                                "/{collection_id}/ltv/weekly",
+                                web::get().to(get_collection_weekly_ltv),
+                            )
+                            .service(
+                                web::resource("/{collection_id}/bots")
+                                    .route(web::get().to(get_collection_bot_patterns))
+                                    .route(web::post().to(add_collection_bot_pattern)),
+                            )
+                            .route(
+                                "/{collection_id}/bots/{bot_pattern_id}",
+                                web::delete().to(remove_collection_bot_pattern),
+                            )
+                            .service(
+                                web::resource("/{collection_id}/schedule")
+                                    .route(web::get().to(get_collection_schedule))
+                                    .route(web::put().to(update_collection_schedule))
+                                    .route(web::delete().to(delete_collection_schedule)),
                             ),
                     )
                     .service(
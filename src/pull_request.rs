@@ -0,0 +1,409 @@
+use crate::commit::SyncOutcome;
+use crate::contributor::{is_bot_account, link_github_account};
+use crate::error::AppError;
+use crate::github::with_backoff;
+use crate::job_queue::{is_cancel_requested, Job, SyncMode};
+use chrono::{DateTime, Utc};
+use log::info;
+use octocrab::models::pulls::PullRequest;
+use octocrab::models::{Author, IssueState};
+use octocrab::params::{pulls::Sort, Direction, State};
+use octocrab::Octocrab;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+
+/// Pull requests whose reviews are fetched between rate limit and
+/// cancellation checks.
+const REVIEWS_BATCH_SIZE: usize = 100;
+
+/// A review comment from the repository-wide listing, which only refers to
+/// its pull request by URL.
+#[derive(Deserialize)]
+struct ReviewComment {
+    id: u64,
+    pull_request_review_id: Option<u64>,
+    pull_request_url: String,
+    user: Option<Author>,
+    created_at: DateTime<Utc>,
+}
+
+#[derive(Serialize)]
+struct ReviewCommentParams {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    since: Option<DateTime<Utc>>,
+    sort: &'static str,
+    direction: &'static str,
+    per_page: u8,
+    page: u32,
+}
+
+/// Syncs the pull requests updated since the previous sync, then the review
+/// comments made since. A full sync fetches every pull request again. Their
+/// reviews are fetched afterwards by `backfill_reviews`.
+pub async fn sync_pull_requests(
+    job: &Job,
+    octocrab: &Octocrab,
+    pool: &PgPool,
+) -> Result<SyncOutcome, AppError> {
+    let started_at = Utc::now();
+    let since = match job.mode {
+        SyncMode::Full => None,
+        SyncMode::Incremental => {
+            sqlx::query_scalar!(
+                "SELECT pull_requests_synced_at FROM repository WHERE repository_id = $1",
+                job.repository_id
+            )
+            .fetch_one(pool)
+            .await?
+        }
+    };
+
+    let mut page: u32 = 1;
+    let mut pull_requests_synced: usize = 0;
+
+    loop {
+        let pull_requests = with_backoff(|| async {
+            octocrab
+                .pulls(&job.owner, &job.name)
+                .list()
+                .state(State::All)
+                .sort(Sort::Updated)
+                .direction(Direction::Descending)
+                .per_page(100)
+                .page(page)
+                .send()
+                .await
+        })
+        .await?;
+        let has_next = pull_requests.next.is_some();
+
+        let mut reached_synced = false;
+        for pull_request in &pull_requests.items {
+            if let (Some(since), Some(updated_at)) = (since, pull_request.updated_at) {
+                if updated_at < since {
+                    reached_synced = true;
+                    break;
+                }
+            }
+            upsert_pull_request(pool, job.repository_id, pull_request).await?;
+            pull_requests_synced += 1;
+        }
+
+        if reached_synced || !has_next {
+            break;
+        }
+        if is_cancel_requested(pool, job.job_id).await? {
+            info!(
+                "Pull request sync for repository {}/{} cancelled after {} pull requests",
+                job.owner, job.name, pull_requests_synced
+            );
+            return Ok(SyncOutcome::Cancelled);
+        }
+        page += 1;
+    }
+
+    if let SyncOutcome::Cancelled = sync_review_comments(job, octocrab, pool, since).await? {
+        return Ok(SyncOutcome::Cancelled);
+    }
+
+    sqlx::query!(
+        "UPDATE repository SET pull_requests_synced_at = $1 WHERE repository_id = $2",
+        started_at,
+        job.repository_id
+    )
+    .execute(pool)
+    .await?;
+
+    info!(
+        "Synced {} pull requests for repository {}/{}",
+        pull_requests_synced, job.owner, job.name
+    );
+
+    Ok(SyncOutcome::Completed)
+}
+
+async fn upsert_pull_request(
+    pool: &PgPool,
+    repository_id: i32,
+    pull_request: &PullRequest,
+) -> Result<(), AppError> {
+    let author = pull_request.user.as_deref();
+    let state = match pull_request.state {
+        Some(IssueState::Closed) => "closed",
+        _ => "open",
+    };
+    let created_at = pull_request.created_at.unwrap_or_else(Utc::now);
+
+    sqlx::query!(
+        r#"
+        INSERT INTO pull_request (repository_id, number, title, state, author_login,
+        author_github_id, is_bot, created_at, updated_at, merged_at, closed_at)
+        VALUES ($1, $2, $3, $4, $5, $6,
+            $7 OR EXISTS (
+                SELECT 1 FROM bot_pattern b
+                WHERE b.collection_id IS NULL AND $5 ~* b.pattern
+            ),
+            $8, $9, $10, $11)
+        ON CONFLICT (repository_id, number) DO UPDATE
+        SET title = EXCLUDED.title,
+            state = EXCLUDED.state,
+            author_login = EXCLUDED.author_login,
+            author_github_id = EXCLUDED.author_github_id,
+            is_bot = EXCLUDED.is_bot,
+            updated_at = EXCLUDED.updated_at,
+            merged_at = EXCLUDED.merged_at,
+            closed_at = EXCLUDED.closed_at
+        "#,
+        repository_id,
+        pull_request.number as i32,
+        pull_request.title,
+        state,
+        author.map(|a| a.login.as_str()),
+        author.map(|a| a.id.0 as i64),
+        author.is_some_and(is_bot_account),
+        created_at,
+        pull_request.updated_at.unwrap_or(created_at),
+        pull_request.merged_at,
+        pull_request.closed_at
+    )
+    .execute(pool)
+    .await?;
+
+    if let Some(author) = author {
+        link_github_account(pool, author).await?;
+    }
+
+    Ok(())
+}
+
+/// Fetches the reviews of pull requests that changed since their reviews
+/// were last fetched, newest first. Reviews cost at least a request per pull
+/// request, so like the commit stats backfill this stops once the token's
+/// remaining rate limit drops to `rate_limit_reserve` and carries on with
+/// the next sync.
+pub async fn backfill_reviews(
+    job: &Job,
+    octocrab: &Octocrab,
+    pool: &PgPool,
+    rate_limit_reserve: usize,
+) -> Result<SyncOutcome, AppError> {
+    let mut reviews_fetched: usize = 0;
+
+    loop {
+        let remaining = with_backoff(|| async { octocrab.ratelimit().get().await })
+            .await?
+            .resources
+            .core
+            .remaining;
+        if remaining <= rate_limit_reserve {
+            info!(
+                "Pausing review sync for repository {}/{} after {} pull requests, {} requests left",
+                job.owner, job.name, reviews_fetched, remaining
+            );
+            return Ok(SyncOutcome::Completed);
+        }
+
+        let batch_size = (remaining - rate_limit_reserve).min(REVIEWS_BATCH_SIZE) as i64;
+        let pull_requests = sqlx::query!(
+            r#"
+            SELECT pull_request_id, number FROM pull_request
+            WHERE repository_id = $1
+            AND (reviews_synced_at IS NULL OR reviews_synced_at < updated_at)
+            ORDER BY updated_at DESC
+            LIMIT $2
+            "#,
+            job.repository_id,
+            batch_size
+        )
+        .fetch_all(pool)
+        .await?;
+        if pull_requests.is_empty() {
+            if reviews_fetched > 0 {
+                info!(
+                    "Fetched reviews for {} pull requests of repository {}/{}",
+                    reviews_fetched, job.owner, job.name
+                );
+            }
+            return Ok(SyncOutcome::Completed);
+        }
+
+        for pull_request in &pull_requests {
+            // Reviews submitted while these are fetched update the pull
+            // request after this, so they are picked up next time
+            let fetched_at = Utc::now();
+            sync_reviews(
+                job,
+                octocrab,
+                pool,
+                pull_request.pull_request_id,
+                pull_request.number as u64,
+            )
+            .await?;
+            sqlx::query!(
+                "UPDATE pull_request SET reviews_synced_at = $2 WHERE pull_request_id = $1",
+                pull_request.pull_request_id,
+                fetched_at
+            )
+            .execute(pool)
+            .await?;
+        }
+        reviews_fetched += pull_requests.len();
+
+        if is_cancel_requested(pool, job.job_id).await? {
+            info!(
+                "Review sync for repository {}/{} cancelled after {} pull requests",
+                job.owner, job.name, reviews_fetched
+            );
+            return Ok(SyncOutcome::Cancelled);
+        }
+    }
+}
+
+async fn sync_reviews(
+    job: &Job,
+    octocrab: &Octocrab,
+    pool: &PgPool,
+    pull_request_id: i32,
+    number: u64,
+) -> Result<(), AppError> {
+    let mut page: u32 = 1;
+
+    loop {
+        let reviews = with_backoff(|| async {
+            octocrab
+                .pulls(&job.owner, &job.name)
+                .list_reviews(number)
+                .per_page(100)
+                .page(page)
+                .send()
+                .await
+        })
+        .await?;
+        let has_next = reviews.next.is_some();
+
+        for review in reviews.items {
+            let reviewer = review.user.as_ref();
+            let state = review
+                .state
+                .and_then(|state| serde_json::to_value(state).ok())
+                .and_then(|state| state.as_str().map(str::to_string))
+                .unwrap_or_else(|| "COMMENTED".to_string());
+
+            sqlx::query!(
+                r#"
+                INSERT INTO pull_request_review (review_id, pull_request_id, state,
+                reviewer_login, reviewer_github_id, is_bot, submitted_at)
+                VALUES ($1, $2, $3, $4, $5,
+                    $6 OR EXISTS (
+                        SELECT 1 FROM bot_pattern b
+                        WHERE b.collection_id IS NULL AND $4 ~* b.pattern
+                    ),
+                    $7)
+                ON CONFLICT (review_id) DO UPDATE
+                SET state = EXCLUDED.state,
+                    reviewer_login = EXCLUDED.reviewer_login,
+                    reviewer_github_id = EXCLUDED.reviewer_github_id,
+                    is_bot = EXCLUDED.is_bot,
+                    submitted_at = EXCLUDED.submitted_at
+                "#,
+                review.id.0 as i64,
+                pull_request_id,
+                state,
+                reviewer.map(|r| r.login.as_str()),
+                reviewer.map(|r| r.id.0 as i64),
+                reviewer.is_some_and(is_bot_account),
+                review.submitted_at
+            )
+            .execute(pool)
+            .await?;
+
+            if let Some(reviewer) = reviewer {
+                link_github_account(pool, reviewer).await?;
+            }
+        }
+
+        if !has_next {
+            return Ok(());
+        }
+        page += 1;
+    }
+}
+
+/// Fetches the repository's review comments in one listing rather than per
+/// pull request. Comments on pull requests that have not been synced are
+/// skipped.
+async fn sync_review_comments(
+    job: &Job,
+    octocrab: &Octocrab,
+    pool: &PgPool,
+    since: Option<DateTime<Utc>>,
+) -> Result<SyncOutcome, AppError> {
+    let route = format!("/repos/{}/{}/pulls/comments", job.owner, job.name);
+    let mut page: u32 = 1;
+
+    loop {
+        let params = ReviewCommentParams {
+            since,
+            sort: "updated",
+            direction: "asc",
+            per_page: 100,
+            page,
+        };
+        let comments: Vec<ReviewComment> =
+            with_backoff(|| async { octocrab.get(&route, Some(&params)).await }).await?;
+        let page_len = comments.len();
+
+        for comment in comments {
+            let Some(number) = comment
+                .pull_request_url
+                .rsplit('/')
+                .next()
+                .and_then(|number| number.parse::<i32>().ok())
+            else {
+                continue;
+            };
+            let author = comment.user.as_ref();
+
+            sqlx::query!(
+                r#"
+                INSERT INTO pull_request_review_comment (comment_id, pull_request_id,
+                review_id, author_login, author_github_id, is_bot, created_at)
+                SELECT $1, pr.pull_request_id, $4, $5, $6,
+                    $7 OR EXISTS (
+                        SELECT 1 FROM bot_pattern b
+                        WHERE b.collection_id IS NULL AND $5 ~* b.pattern
+                    ),
+                    $8
+                FROM pull_request pr
+                WHERE pr.repository_id = $2 AND pr.number = $3
+                ON CONFLICT (comment_id) DO UPDATE
+                SET author_login = EXCLUDED.author_login,
+                    author_github_id = EXCLUDED.author_github_id,
+                    is_bot = EXCLUDED.is_bot
+                "#,
+                comment.id as i64,
+                job.repository_id,
+                number,
+                comment.pull_request_review_id.map(|id| id as i64),
+                author.map(|a| a.login.as_str()),
+                author.map(|a| a.id.0 as i64),
+                author.is_some_and(is_bot_account),
+                comment.created_at
+            )
+            .execute(pool)
+            .await?;
+
+            if let Some(author) = author {
+                link_github_account(pool, author).await?;
+            }
+        }
+
+        if page_len < 100 {
+            return Ok(SyncOutcome::Completed);
+        }
+        if is_cancel_requested(pool, job.job_id).await? {
+            return Ok(SyncOutcome::Cancelled);
+        }
+        page += 1;
+    }
+}
@@ -1,14 +1,18 @@
+use crate::auth_utils::get_account_id;
+use crate::collection::check_repository_owner;
 use crate::error::AppError;
 use crate::github::{get_github_client, get_github_token};
 use crate::growth_accounting::{
-    ltv_cohorts_cumulative, mau_growth_accounting, mrr_growth_accounting,
-    LTVCohortsCumulativeResult, MAUGrowthAccountingResult, MRRGrowthAccountingResult,
+    dau_query, ltv_cohorts_cumulative, ltv_weekly_cohorts_cumulative, mau_growth_accounting,
+    mau_retention_by_cohort, mrr_growth_accounting, retention_triangle, stickiness, summarize,
+    DauScope, Granularity, GrowthAccountingParams, LTVCohortsCumulativeResult,
+    MAUGrowthAccountingResult, MRRGrowthAccountingResult, TrailingAverages,
 };
-use crate::job_queue::Job;
+use crate::job_queue::{EnqueuedJob, SyncMode};
 use crate::types::PaginatedResponse;
 use crate::AppState;
 use actix_web::web::Query;
-use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use actix_web::{web, HttpRequest, HttpResponse, Responder, ResponseError};
 use chrono::{DateTime, Utc};
 use log::{error, info};
 use octocrab::Octocrab;
@@ -289,20 +293,7 @@ pub async fn upsert_repository(
             )
             .await?;
 
-            let github_token = match get_github_token(&req) {
-                Ok(token) => token,
-                Err(e) => {
-                    error!("Failed to get github_token from session: {:?}", e);
-                    return Err(AppError::Unauthorized("User not authenticated".into()));
-                }
-            };
-            let job = Job {
-                repository_id: repository.repository_id,
-                owner: repository.owner.clone(),
-                name: repository.name.clone(),
-                github_token,
-            };
-            state.job_queue.push(job).await;
+            enqueue_sync(state, req, repository.repository_id, SyncMode::Incremental).await?;
 
             Ok(repository)
         }
@@ -310,6 +301,27 @@ pub async fn upsert_repository(
     }
 }
 
+/// Stores the caller's GitHub token so the worker can run the sync with it,
+/// then queues the sync under the caller's account.
+async fn enqueue_sync(
+    state: &AppState,
+    req: &HttpRequest,
+    repository_id: i32,
+    mode: SyncMode,
+) -> Result<EnqueuedJob, AppError> {
+    let account_id = get_account_id(req)?;
+    let github_token = get_github_token(req)?;
+    state
+        .token_store
+        .save(&state.db_pool, account_id, &github_token)
+        .await?;
+
+    Ok(state
+        .job_queue
+        .push(repository_id, Some(account_id), mode)
+        .await?)
+}
+
 async fn get_repository_by_id(pool: &PgPool, id: i32) -> Result<Option<Repository>, sqlx::Error> {
     sqlx::query_as!(
         Repository,
@@ -387,20 +399,19 @@ pub async fn create_repository(
     }
 }
 
+#[derive(Deserialize)]
+pub struct SyncRepositoryQuery {
+    mode: Option<SyncMode>,
+}
+
 pub async fn sync_repository(
     state: web::Data<AppState>,
     path: web::Path<(String, String)>,
+    query: Query<SyncRepositoryQuery>,
     req: HttpRequest,
 ) -> impl Responder {
     let (owner, name) = path.into_inner();
-
-    let github_token = match get_github_token(&req) {
-        Ok(token) => token,
-        Err(_) => {
-            error!("Failed to get access token from claims");
-            return HttpResponse::Unauthorized().finish();
-        }
-    };
+    let mode = query.mode.unwrap_or_default();
 
     let github_client = match get_github_client(&req) {
         Ok(client) => client,
@@ -418,19 +429,35 @@ pub async fn sync_repository(
                     // Update repository with latest GitHub data
                     match update_repository_from_github(&state.db_pool, &gh_repo).await {
                         Ok(updated_repo) => {
-                            let job = Job {
-                                repository_id: updated_repo.repository_id,
-                                owner: owner.clone(),
-                                name: name.clone(),
-                                github_token,
-                            };
-                            state.job_queue.push(job).await;
-                            info!("Queued sync job for repository: {}/{}", owner, name);
-                            HttpResponse::Accepted().json(json!({
-                                "message": "Repository sync job queued",
-                                "owner": owner,
-                                "name": name
-                            }))
+                            match enqueue_sync(&state, &req, updated_repo.repository_id, mode).await
+                            {
+                                Ok(enqueued) if !enqueued.mode_applied => {
+                                    HttpResponse::Conflict().json(json!({
+                                        "error": "An incremental sync is already running, so full mode was not applied; request it again once the job has finished",
+                                        "job_id": enqueued.job_id,
+                                        "owner": owner,
+                                        "name": name
+                                    }))
+                                }
+                                Ok(enqueued) => {
+                                    let message = if enqueued.is_new {
+                                        info!("Queued sync job for repository: {}/{}", owner, name);
+                                        "Repository sync job queued"
+                                    } else {
+                                        "Repository sync job already queued or running"
+                                    };
+                                    HttpResponse::Accepted().json(json!({
+                                        "message": message,
+                                        "job_id": enqueued.job_id,
+                                        "owner": owner,
+                                        "name": name
+                                    }))
+                                }
+                                Err(e) => {
+                                    error!("Failed to queue sync job: {:?}", e);
+                                    e.error_response()
+                                }
+                            }
                         }
                         Err(e) => {
                             error!("Failed to update repository data: {:?}", e);
@@ -457,6 +484,94 @@ pub async fn sync_repository(
     }
 }
 
+#[derive(Serialize)]
+pub struct SyncedBranch {
+    branch: String,
+    head_sha: String,
+    synced_at: DateTime<Utc>,
+}
+
+#[derive(Serialize)]
+pub struct RepositoryBranches {
+    /// Glob patterns selecting branches synced in addition to the default
+    /// branch.
+    patterns: Vec<String>,
+    branches: Vec<SyncedBranch>,
+}
+
+#[derive(Deserialize)]
+pub struct UpdateRepositoryBranches {
+    patterns: Vec<String>,
+}
+
+pub async fn get_repository_branches(
+    state: web::Data<AppState>,
+    path: web::Path<(String, String)>,
+) -> Result<HttpResponse, AppError> {
+    let (owner, name) = path.into_inner();
+    let repository = sqlx::query!(
+        "SELECT repository_id, branch_patterns FROM repository WHERE owner = $1 AND name = $2",
+        owner,
+        name
+    )
+    .fetch_optional(&state.db_pool)
+    .await?
+    .ok_or_else(|| AppError::NotFound("Repository not found in database".into()))?;
+
+    let branches = sqlx::query_as!(
+        SyncedBranch,
+        r#"
+        SELECT branch, head_sha, synced_at
+        FROM repository_branch
+        WHERE repository_id = $1
+        ORDER BY branch
+        "#,
+        repository.repository_id
+    )
+    .fetch_all(&state.db_pool)
+    .await?;
+
+    Ok(HttpResponse::Ok().json(RepositoryBranches {
+        patterns: repository.branch_patterns,
+        branches,
+    }))
+}
+
+/// Replaces the branch patterns. Branches that no longer match keep the
+/// commits already synced from them.
+pub async fn update_repository_branches(
+    state: web::Data<AppState>,
+    req: HttpRequest,
+    path: web::Path<(String, String)>,
+    body: web::Json<UpdateRepositoryBranches>,
+) -> Result<HttpResponse, AppError> {
+    let (owner, name) = path.into_inner();
+    let patterns = body.into_inner().patterns;
+    for pattern in &patterns {
+        if let Err(e) = glob::Pattern::new(pattern) {
+            return Err(AppError::BadRequest(format!(
+                "Invalid branch pattern {}: {}",
+                pattern, e
+            )));
+        }
+    }
+
+    let repository_id = get_repository_id(&state.db_pool, &owner, &name)
+        .await?
+        .ok_or_else(|| AppError::NotFound("Repository not found in database".into()))?;
+    check_repository_owner(&state.db_pool, &req, repository_id).await?;
+
+    sqlx::query!(
+        "UPDATE repository SET branch_patterns = $1 WHERE repository_id = $2",
+        &patterns,
+        repository_id
+    )
+    .execute(&state.db_pool)
+    .await?;
+
+    Ok(HttpResponse::Ok().json(json!({ "patterns": patterns })))
+}
+
 async fn get_repository_id(
     pool: &PgPool,
     owner: &str,
@@ -473,16 +588,27 @@ async fn get_repository_id(
     Ok(result.map(|row| row.repository_id))
 }
 
+async fn repository_scope(pool: &PgPool, owner: &str, name: &str) -> Result<DauScope, AppError> {
+    get_repository_id(pool, owner, name)
+        .await?
+        .map(DauScope::Repository)
+        .ok_or_else(|| AppError::NotFound("Repository not found in database".into()))
+}
+
 pub async fn get_repository_ga(
     state: web::Data<AppState>,
     path: web::Path<(String, String)>,
+    params: Query<GrowthAccountingParams>,
 ) -> impl Responder {
     let (owner, name) = path.into_inner();
+    if let Err(message) = params.validate() {
+        return HttpResponse::BadRequest().json(json!({ "error": message }));
+    }
 
     // Check if the repository exists in our database
     match get_repository_id(&state.db_pool, &owner, &name).await {
         Ok(Some(repository_id)) => {
-            match fetch_growth_accounting(&state.db_pool, repository_id).await {
+            match fetch_growth_accounting(&state.db_pool, repository_id, &params).await {
                 Ok(results) => HttpResponse::Ok().json(results),
                 Err(e) => {
                     error!("Error fetching growth accounting data: {:?}", e);
@@ -507,40 +633,81 @@ pub async fn get_repository_ga(
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct GrowthAccountingResult {
+    granularity: Granularity,
     mau_growth_accounting: Vec<MAUGrowthAccountingResult>,
     mrr_growth_accounting: Vec<MRRGrowthAccountingResult>,
     ltv_cumulative_cohort: Vec<LTVCohortsCumulativeResult>,
+    summary: Vec<TrailingAverages>,
 }
 
 async fn fetch_growth_accounting(
     pool: &PgPool,
     repository_id: i32,
+    params: &GrowthAccountingParams,
 ) -> Result<GrowthAccountingResult, sqlx::Error> {
-    let dau_query = format!(
-        r#"
-        SELECT
-            author AS user_id,
-            date_trunc('day',
-                "date") AS dt,
-            count(*) AS inc_amt
-        FROM
-            "commit"
-        WHERE
-            repository_id = {}
-        GROUP BY
-            1,
-            2
-        "#,
-        repository_id
-    );
+    let scope = DauScope::Repository(repository_id);
+    let activity_dau = dau_query(scope, params, None);
+    let metric_dau = dau_query(scope, params, params.metric);
 
-    let mau_ga = mau_growth_accounting(pool, dau_query.clone()).await?;
-    let mrr_ga = mrr_growth_accounting(pool, dau_query.clone()).await?;
-    let ltv_cumulative = ltv_cohorts_cumulative(pool, dau_query.clone()).await?;
+    let mau_ga = mau_growth_accounting(pool, activity_dau, params).await?;
+    let mrr_ga = mrr_growth_accounting(pool, metric_dau.clone(), params).await?;
+    let ltv_cumulative = ltv_cohorts_cumulative(pool, metric_dau, params).await?;
+    let summary = summarize(&mau_ga, &mrr_ga, params.granularity);
 
     Ok(GrowthAccountingResult {
+        granularity: params.granularity,
         mau_growth_accounting: mau_ga,
         mrr_growth_accounting: mrr_ga,
         ltv_cumulative_cohort: ltv_cumulative,
+        summary,
     })
 }
+
+pub async fn get_repository_stickiness(
+    state: web::Data<AppState>,
+    path: web::Path<(String, String)>,
+    params: Query<GrowthAccountingParams>,
+) -> Result<HttpResponse, AppError> {
+    params
+        .validate()
+        .map_err(|e| AppError::BadRequest(e.into()))?;
+    let (owner, name) = path.into_inner();
+    let scope = repository_scope(&state.db_pool, &owner, &name).await?;
+
+    let dau = dau_query(scope, &params, None);
+    let results = stickiness(&state.db_pool, dau, &params).await?;
+    Ok(HttpResponse::Ok().json(results))
+}
+
+pub async fn get_repository_retention(
+    state: web::Data<AppState>,
+    path: web::Path<(String, String)>,
+    params: Query<GrowthAccountingParams>,
+) -> Result<HttpResponse, AppError> {
+    params
+        .validate()
+        .map_err(|e| AppError::BadRequest(e.into()))?;
+    let (owner, name) = path.into_inner();
+    let scope = repository_scope(&state.db_pool, &owner, &name).await?;
+
+    let dau = dau_query(scope, &params, None);
+    let metric_dau = dau_query(scope, &params, params.metric);
+    let rows = mau_retention_by_cohort(&state.db_pool, dau, metric_dau, &params).await?;
+    Ok(HttpResponse::Ok().json(retention_triangle(rows)))
+}
+
+pub async fn get_repository_weekly_ltv(
+    state: web::Data<AppState>,
+    path: web::Path<(String, String)>,
+    params: Query<GrowthAccountingParams>,
+) -> Result<HttpResponse, AppError> {
+    params
+        .validate()
+        .map_err(|e| AppError::BadRequest(e.into()))?;
+    let (owner, name) = path.into_inner();
+    let scope = repository_scope(&state.db_pool, &owner, &name).await?;
+
+    let dau = dau_query(scope, &params, params.metric);
+    let results = ltv_weekly_cohorts_cumulative(&state.db_pool, dau, &params).await?;
+    Ok(HttpResponse::Ok().json(results))
+}
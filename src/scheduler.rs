@@ -0,0 +1,310 @@
+use crate::collection::{check_collection_owner, check_repository_owner};
+use crate::error::AppError;
+use crate::job_queue::{JobQueue, SyncMode};
+use crate::settings::SchedulerConfig;
+use crate::AppState;
+use actix_web::{web, HttpRequest, HttpResponse};
+use chrono::{DateTime, Utc};
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Periodically queues incremental syncs for repositories whose last sync is
+/// older than their effective schedule. Each job runs as the account whose
+/// sync of the repository last succeeded, or with the server's own GitHub
+/// token when no such account still has a token stored.
+pub async fn run_scheduler(queue: Arc<JobQueue>, pool: PgPool, config: SchedulerConfig) {
+    let mut ticker = tokio::time::interval(Duration::from_secs(config.tick_interval_secs));
+
+    loop {
+        ticker.tick().await;
+
+        let repositories = match fetch_stale_repositories(&pool, &config).await {
+            Ok(repositories) => repositories,
+            Err(e) => {
+                error!("Failed to look up repositories due for a sync: {:?}", e);
+                continue;
+            }
+        };
+
+        for (repository_id, account_id) in repositories {
+            match queue
+                .push(repository_id, account_id, SyncMode::Incremental)
+                .await
+            {
+                Ok(enqueued) if enqueued.is_new => info!(
+                    "Scheduled sync job {} for repository {}",
+                    enqueued.job_id, repository_id
+                ),
+                Ok(_) => {}
+                Err(e) => error!(
+                    "Failed to schedule sync for repository {}: {:?}",
+                    repository_id, e
+                ),
+            }
+        }
+    }
+}
+
+/// A repository's own schedule wins over its collections' schedules; across
+/// collections the most frequent enabled schedule applies. Repositories that
+/// had a job queued within their interval are skipped so failing syncs are
+/// not retried on every tick.
+async fn fetch_stale_repositories(
+    pool: &PgPool,
+    config: &SchedulerConfig,
+) -> Result<Vec<(i32, Option<i32>)>, sqlx::Error> {
+    let rows = sqlx::query!(
+        r#"
+        WITH effective_schedule AS (
+            SELECT
+                r.repository_id,
+                r.indexed_at,
+                COALESCE(rs.enabled, cs.enabled, true) AS enabled,
+                COALESCE(rs.interval_minutes, cs.interval_minutes, $1) AS interval_minutes
+            FROM repository r
+            LEFT JOIN sync_schedule rs ON rs.repository_id = r.repository_id
+            LEFT JOIN LATERAL (
+                SELECT
+                    bool_or(s.enabled) AS enabled,
+                    MIN(s.interval_minutes) FILTER (WHERE s.enabled) AS interval_minutes
+                FROM sync_schedule s
+                JOIN collection_repository cr ON cr.collection_id = s.collection_id
+                WHERE cr.repository_id = r.repository_id
+            ) cs ON true
+        )
+        SELECT e.repository_id, (
+            SELECT j.account_id FROM sync_job j
+            JOIN github_token t ON t.account_id = j.account_id
+            WHERE j.repository_id = e.repository_id AND j.status = 'succeeded'
+            ORDER BY j.finished_at DESC
+            LIMIT 1
+        ) AS account_id
+        FROM effective_schedule e
+        WHERE e.enabled
+        AND (
+            e.indexed_at IS NULL
+            OR e.indexed_at < NOW() - make_interval(mins => e.interval_minutes)
+        )
+        AND NOT EXISTS (
+            SELECT 1 FROM sync_job j
+            WHERE j.repository_id = e.repository_id
+            AND j.created_at > NOW() - make_interval(mins => e.interval_minutes)
+        )
+        ORDER BY e.indexed_at NULLS FIRST
+        "#,
+        config.resync_interval_minutes
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| (row.repository_id, row.account_id))
+        .collect())
+}
+
+#[derive(Debug, Serialize)]
+pub struct SyncSchedule {
+    sync_schedule_id: i32,
+    repository_id: Option<i32>,
+    collection_id: Option<i32>,
+    interval_minutes: i32,
+    enabled: bool,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+}
+
+#[derive(Deserialize)]
+pub struct UpdateSyncSchedule {
+    interval_minutes: i32,
+    enabled: Option<bool>,
+}
+
+async fn get_repository_id(pool: &PgPool, owner: &str, name: &str) -> Result<i32, AppError> {
+    sqlx::query_scalar!(
+        "SELECT repository_id FROM repository WHERE owner = $1 AND name = $2",
+        owner,
+        name
+    )
+    .fetch_optional(pool)
+    .await?
+    .ok_or_else(|| AppError::NotFound("Repository not found in database".into()))
+}
+
+pub async fn get_repository_schedule(
+    state: web::Data<AppState>,
+    path: web::Path<(String, String)>,
+) -> Result<HttpResponse, AppError> {
+    let (owner, name) = path.into_inner();
+    let repository_id = get_repository_id(&state.db_pool, &owner, &name).await?;
+
+    let schedule = sqlx::query_as!(
+        SyncSchedule,
+        r#"
+        SELECT sync_schedule_id, repository_id, collection_id, interval_minutes,
+        enabled, created_at, updated_at
+        FROM sync_schedule
+        WHERE repository_id = $1
+        "#,
+        repository_id
+    )
+    .fetch_optional(&state.db_pool)
+    .await?;
+
+    match schedule {
+        Some(schedule) => Ok(HttpResponse::Ok().json(schedule)),
+        None => Err(AppError::NotFound(
+            "Repository has no schedule override".into(),
+        )),
+    }
+}
+
+pub async fn update_repository_schedule(
+    state: web::Data<AppState>,
+    req: HttpRequest,
+    path: web::Path<(String, String)>,
+    schedule: web::Json<UpdateSyncSchedule>,
+) -> Result<HttpResponse, AppError> {
+    if schedule.interval_minutes <= 0 {
+        return Err(AppError::BadRequest(
+            "interval_minutes must be positive".into(),
+        ));
+    }
+    let (owner, name) = path.into_inner();
+    let repository_id = get_repository_id(&state.db_pool, &owner, &name).await?;
+    check_repository_owner(&state.db_pool, &req, repository_id).await?;
+
+    let schedule = sqlx::query_as!(
+        SyncSchedule,
+        r#"
+        INSERT INTO sync_schedule (repository_id, interval_minutes, enabled)
+        VALUES ($1, $2, COALESCE($3, true))
+        ON CONFLICT (repository_id) DO UPDATE
+        SET interval_minutes = EXCLUDED.interval_minutes,
+            enabled = COALESCE($3, sync_schedule.enabled),
+            updated_at = NOW()
+        RETURNING sync_schedule_id, repository_id, collection_id, interval_minutes,
+        enabled, created_at, updated_at
+        "#,
+        repository_id,
+        schedule.interval_minutes,
+        schedule.enabled
+    )
+    .fetch_one(&state.db_pool)
+    .await?;
+
+    Ok(HttpResponse::Ok().json(schedule))
+}
+
+pub async fn delete_repository_schedule(
+    state: web::Data<AppState>,
+    req: HttpRequest,
+    path: web::Path<(String, String)>,
+) -> Result<HttpResponse, AppError> {
+    let (owner, name) = path.into_inner();
+    let repository_id = get_repository_id(&state.db_pool, &owner, &name).await?;
+    check_repository_owner(&state.db_pool, &req, repository_id).await?;
+
+    let result = sqlx::query!(
+        "DELETE FROM sync_schedule WHERE repository_id = $1",
+        repository_id
+    )
+    .execute(&state.db_pool)
+    .await?;
+
+    if result.rows_affected() > 0 {
+        Ok(HttpResponse::NoContent().finish())
+    } else {
+        Ok(HttpResponse::NotFound().finish())
+    }
+}
+
+pub async fn get_collection_schedule(
+    state: web::Data<AppState>,
+    req: HttpRequest,
+    collection_id: web::Path<i32>,
+) -> Result<HttpResponse, AppError> {
+    let collection_id = collection_id.into_inner();
+    check_collection_owner(&state.db_pool, &req, collection_id).await?;
+
+    let schedule = sqlx::query_as!(
+        SyncSchedule,
+        r#"
+        SELECT sync_schedule_id, repository_id, collection_id, interval_minutes,
+        enabled, created_at, updated_at
+        FROM sync_schedule
+        WHERE collection_id = $1
+        "#,
+        collection_id
+    )
+    .fetch_optional(&state.db_pool)
+    .await?;
+
+    match schedule {
+        Some(schedule) => Ok(HttpResponse::Ok().json(schedule)),
+        None => Err(AppError::NotFound(
+            "Collection has no schedule override".into(),
+        )),
+    }
+}
+
+pub async fn update_collection_schedule(
+    state: web::Data<AppState>,
+    req: HttpRequest,
+    collection_id: web::Path<i32>,
+    schedule: web::Json<UpdateSyncSchedule>,
+) -> Result<HttpResponse, AppError> {
+    if schedule.interval_minutes <= 0 {
+        return Err(AppError::BadRequest(
+            "interval_minutes must be positive".into(),
+        ));
+    }
+    let collection_id = collection_id.into_inner();
+    check_collection_owner(&state.db_pool, &req, collection_id).await?;
+
+    let schedule = sqlx::query_as!(
+        SyncSchedule,
+        r#"
+        INSERT INTO sync_schedule (collection_id, interval_minutes, enabled)
+        VALUES ($1, $2, COALESCE($3, true))
+        ON CONFLICT (collection_id) DO UPDATE
+        SET interval_minutes = EXCLUDED.interval_minutes,
+            enabled = COALESCE($3, sync_schedule.enabled),
+            updated_at = NOW()
+        RETURNING sync_schedule_id, repository_id, collection_id, interval_minutes,
+        enabled, created_at, updated_at
+        "#,
+        collection_id,
+        schedule.interval_minutes,
+        schedule.enabled
+    )
+    .fetch_one(&state.db_pool)
+    .await?;
+
+    Ok(HttpResponse::Ok().json(schedule))
+}
+
+pub async fn delete_collection_schedule(
+    state: web::Data<AppState>,
+    req: HttpRequest,
+    collection_id: web::Path<i32>,
+) -> Result<HttpResponse, AppError> {
+    let collection_id = collection_id.into_inner();
+    check_collection_owner(&state.db_pool, &req, collection_id).await?;
+
+    let result = sqlx::query!(
+        "DELETE FROM sync_schedule WHERE collection_id = $1",
+        collection_id
+    )
+    .execute(&state.db_pool)
+    .await?;
+
+    if result.rows_affected() > 0 {
+        Ok(HttpResponse::NoContent().finish())
+    } else {
+        Ok(HttpResponse::NotFound().finish())
+    }
+}
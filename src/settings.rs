@@ -0,0 +1,148 @@
+use config::{Config, ConfigError, Environment, File};
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize)]
+pub struct DatabaseConfig {
+    pub host: String,
+    pub port: u16,
+    pub name: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct JobConfig {
+    /// Attempts a sync job gets before it is dead-lettered.
+    #[serde(default = "default_max_attempts")]
+    pub max_attempts: i32,
+    /// Delay before the first retry; doubled on every further attempt.
+    #[serde(default = "default_retry_base_delay_secs")]
+    pub retry_base_delay_secs: u64,
+    /// Number of jobs processed concurrently.
+    #[serde(default = "default_workers")]
+    pub workers: usize,
+    /// Running jobs allowed per GitHub token, so one user's rate limit is
+    /// not shared by too many syncs at once.
+    #[serde(default = "default_max_jobs_per_token")]
+    pub max_jobs_per_token: i64,
+    /// How long an idle worker waits for a wake-up before polling anyway,
+    /// which picks up retries that have become due.
+    #[serde(default = "default_poll_interval_secs")]
+    pub poll_interval_secs: u64,
+    /// Core API requests the commit stats and pull request review backfills
+    /// leave unused, so fetching them one at a time cannot starve listings
+    /// or requests made on the user's behalf.
+    #[serde(default = "default_stats_rate_limit_reserve")]
+    pub stats_rate_limit_reserve: usize,
+}
+
+fn default_max_attempts() -> i32 {
+    5
+}
+
+fn default_retry_base_delay_secs() -> u64 {
+    30
+}
+
+fn default_workers() -> usize {
+    4
+}
+
+fn default_max_jobs_per_token() -> i64 {
+    2
+}
+
+fn default_poll_interval_secs() -> u64 {
+    10
+}
+
+fn default_stats_rate_limit_reserve() -> usize {
+    500
+}
+
+impl Default for JobConfig {
+    fn default() -> Self {
+        Self {
+            max_attempts: default_max_attempts(),
+            retry_base_delay_secs: default_retry_base_delay_secs(),
+            workers: default_workers(),
+            max_jobs_per_token: default_max_jobs_per_token(),
+            poll_interval_secs: default_poll_interval_secs(),
+            stats_rate_limit_reserve: default_stats_rate_limit_reserve(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct SchedulerConfig {
+    #[serde(default = "default_scheduler_enabled")]
+    pub enabled: bool,
+    /// Re-sync repositories whose last sync is older than this, unless a
+    /// schedule override in the database says otherwise.
+    #[serde(default = "default_resync_interval_minutes")]
+    pub resync_interval_minutes: i32,
+    /// How often the scheduler looks for stale repositories.
+    #[serde(default = "default_tick_interval_secs")]
+    pub tick_interval_secs: u64,
+}
+
+fn default_scheduler_enabled() -> bool {
+    true
+}
+
+fn default_resync_interval_minutes() -> i32 {
+    24 * 60
+}
+
+fn default_tick_interval_secs() -> u64 {
+    300
+}
+
+impl Default for SchedulerConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_scheduler_enabled(),
+            resync_interval_minutes: default_resync_interval_minutes(),
+            tick_interval_secs: default_tick_interval_secs(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Settings {
+    pub database: DatabaseConfig,
+    #[serde(default)]
+    pub jobs: JobConfig,
+    #[serde(default)]
+    pub scheduler: SchedulerConfig,
+}
+
+impl Settings {
+    pub fn new() -> Result<Self, ConfigError> {
+        // Load .env file
+        dotenv::dotenv().ok();
+
+        let s = Config::builder()
+            .add_source(File::with_name("config"))
+            .add_source(Environment::with_prefix("app"))
+            .build()?;
+
+        let settings: Settings = s.try_deserialize()?;
+        settings.validate()?;
+        Ok(settings)
+    }
+
+    /// Rejects values the background tasks cannot run with, which would
+    /// otherwise only fail once the task has been spawned.
+    fn validate(&self) -> Result<(), ConfigError> {
+        if self.scheduler.tick_interval_secs == 0 {
+            return Err(ConfigError::Message(
+                "scheduler.tick_interval_secs must be at least 1".into(),
+            ));
+        }
+        if self.scheduler.resync_interval_minutes < 1 {
+            return Err(ConfigError::Message(
+                "scheduler.resync_interval_minutes must be at least 1".into(),
+            ));
+        }
+        Ok(())
+    }
+}
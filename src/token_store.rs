@@ -0,0 +1,113 @@
+use crate::error::AppError;
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use sqlx::PgPool;
+use std::env;
+
+const NONCE_LEN: usize = 12;
+
+/// Keeps users' GitHub access tokens in Postgres, encrypted with AES-256-GCM
+/// under `TOKEN_ENCRYPTION_KEY`, so sync jobs only need to reference an
+/// account. Jobs without an account run with the server's `GITHUB_TOKEN`.
+pub struct TokenStore {
+    cipher: Aes256Gcm,
+    service_token: Option<String>,
+}
+
+impl TokenStore {
+    pub fn from_env() -> Result<Self, String> {
+        let key = env::var("TOKEN_ENCRYPTION_KEY")
+            .map_err(|_| "TOKEN_ENCRYPTION_KEY is not set".to_string())?;
+        let key = hex::decode(key.trim())
+            .map_err(|e| format!("TOKEN_ENCRYPTION_KEY is not valid hex: {}", e))?;
+        if key.len() != 32 {
+            return Err("TOKEN_ENCRYPTION_KEY must be 32 bytes (64 hex characters)".to_string());
+        }
+
+        Ok(Self {
+            cipher: Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key)),
+            service_token: env::var("GITHUB_TOKEN").ok(),
+        })
+    }
+
+    pub fn has_service_token(&self) -> bool {
+        self.service_token.is_some()
+    }
+
+    pub async fn save(&self, pool: &PgPool, account_id: i32, token: &str) -> Result<(), AppError> {
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = self
+            .cipher
+            .encrypt(&nonce, token.as_bytes())
+            .map_err(|_| AppError::InternalServerError("Failed to encrypt token".into()))?;
+        let mut encrypted_token = nonce.to_vec();
+        encrypted_token.extend_from_slice(&ciphertext);
+
+        sqlx::query!(
+            r#"
+            INSERT INTO github_token (account_id, encrypted_token)
+            VALUES ($1, $2)
+            ON CONFLICT (account_id) DO UPDATE
+            SET encrypted_token = EXCLUDED.encrypted_token,
+                updated_at = NOW()
+            "#,
+            account_id,
+            encrypted_token
+        )
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Resolves the token a job should run with. Fails with `Unauthorized`
+    /// when there is no usable token, which is not worth retrying.
+    pub async fn resolve(
+        &self,
+        pool: &PgPool,
+        account_id: Option<i32>,
+    ) -> Result<String, AppError> {
+        let account_id = match account_id {
+            Some(account_id) => account_id,
+            None => {
+                return self
+                    .service_token
+                    .clone()
+                    .ok_or_else(|| AppError::Unauthorized("GITHUB_TOKEN is not configured".into()))
+            }
+        };
+
+        let row = sqlx::query!(
+            "SELECT encrypted_token FROM github_token WHERE account_id = $1",
+            account_id
+        )
+        .fetch_optional(pool)
+        .await?
+        .ok_or_else(|| {
+            AppError::Unauthorized(format!("No GitHub token stored for account {}", account_id))
+        })?;
+
+        if row.encrypted_token.len() <= NONCE_LEN {
+            return Err(AppError::InternalServerError(
+                "Stored GitHub token is malformed".into(),
+            ));
+        }
+        let (nonce, ciphertext) = row.encrypted_token.split_at(NONCE_LEN);
+        let token = self
+            .cipher
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| AppError::InternalServerError("Failed to decrypt token".into()))?;
+
+        String::from_utf8(token)
+            .map_err(|_| AppError::InternalServerError("Stored GitHub token is malformed".into()))
+    }
+
+    /// Forgets an account's token once GitHub has rejected it.
+    pub async fn revoke(&self, pool: &PgPool, account_id: i32) -> Result<(), AppError> {
+        sqlx::query!("DELETE FROM github_token WHERE account_id = $1", account_id)
+            .execute(pool)
+            .await?;
+
+        Ok(())
+    }
+}